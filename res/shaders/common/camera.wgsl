@@ -0,0 +1,11 @@
+// Camera uniform, define CAMERA_GROUP before including it to bind it at another group
+
+#ifndef CAMERA_GROUP
+#define CAMERA_GROUP 1
+#endif
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+};
+@group(CAMERA_GROUP) @binding(0)
+var<uniform> camera: CameraUniform;
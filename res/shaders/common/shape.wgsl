@@ -0,0 +1,34 @@
+// Vertex shader shared by the shapes, define TEXTURED before including it
+// to pass on texture coordinates instead of a color
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+#ifdef TEXTURED
+    @location(1) tex_coords: vec3<f32>,
+#else
+    @location(1) color: vec3<f32>,
+#endif
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+#ifdef TEXTURED
+    @location(0) tex_coords: vec2<f32>,
+#else
+    @location(0) color: vec3<f32>,
+#endif
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+#ifdef TEXTURED
+    out.tex_coords = model.tex_coords.xy;
+#else
+    out.color = model.color;
+#endif
+    out.clip_position = vec4<f32>(model.position, 1.0);
+    return out;
+}
@@ -0,0 +1,49 @@
+struct BloomSettings {
+    threshold: f32,
+    intensity: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> settings: BloomSettings;
+
+// Only used by the composite pass
+@group(2) @binding(0)
+var t_bloom: texture_2d<f32>;
+@group(2) @binding(1)
+var s_bloom: sampler;
+
+@fragment
+fn fs_threshold(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, in.tex_coords).rgb;
+    let brightness = max(color.r, max(color.g, color.b));
+    let contribution = max(brightness - settings.threshold, 0.0) / max(brightness, 0.0001);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+fn blur(uv: vec2<f32>, direction: vec2<f32>) -> vec4<f32> {
+    let texel = direction / vec2<f32>(textureDimensions(t_input));
+    // 9 tap gaussian using linear filtering to fetch two texels per sample
+    var color = textureSample(t_input, s_input, uv).rgb * 0.2270270270;
+    color += textureSample(t_input, s_input, uv + texel * 1.3846153846).rgb * 0.3162162162;
+    color += textureSample(t_input, s_input, uv - texel * 1.3846153846).rgb * 0.3162162162;
+    color += textureSample(t_input, s_input, uv + texel * 3.2307692308).rgb * 0.0702702703;
+    color += textureSample(t_input, s_input, uv - texel * 3.2307692308).rgb * 0.0702702703;
+    return vec4<f32>(color, 1.0);
+}
+
+@fragment
+fn fs_blur_horizontal(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return blur(in.tex_coords, vec2<f32>(1.0, 0.0));
+}
+
+@fragment
+fn fs_blur_vertical(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return blur(in.tex_coords, vec2<f32>(0.0, 1.0));
+}
+
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, in.tex_coords);
+    let bloom = textureSample(t_bloom, s_bloom, in.tex_coords).rgb;
+    return vec4<f32>(color.rgb + bloom * settings.intensity, color.a);
+}
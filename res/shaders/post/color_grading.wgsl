@@ -0,0 +1,22 @@
+struct ColorGradingSettings {
+    intensity: f32,
+    lut_size: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> settings: ColorGradingSettings;
+
+@group(2) @binding(0)
+var t_lut: texture_3d<f32>;
+@group(2) @binding(1)
+var s_lut: sampler;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, in.tex_coords);
+    // Sample at texel centers so the outermost entries of the LUT map to 0 and 1
+    let scale = (settings.lut_size - 1.0) / settings.lut_size;
+    let offset = 0.5 / settings.lut_size;
+    let graded = textureSample(t_lut, s_lut, saturate(color.rgb) * scale + offset).rgb;
+    return vec4<f32>(mix(color.rgb, graded, settings.intensity), color.a);
+}
@@ -0,0 +1,4 @@
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_input, s_input, in.tex_coords);
+}
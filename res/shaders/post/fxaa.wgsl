@@ -0,0 +1,53 @@
+// FXAA based on the approximation by Timothy Lottes
+
+struct FxaaSettings {
+    span_max: f32,
+    reduce_mul: f32,
+    reduce_min: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> settings: FxaaSettings;
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_input));
+    let uv = in.tex_coords;
+
+    let color_m = textureSample(t_input, s_input, uv);
+    let luma_nw = luma(textureSample(t_input, s_input, uv + vec2<f32>(-1.0, -1.0) * texel).rgb);
+    let luma_ne = luma(textureSample(t_input, s_input, uv + vec2<f32>(1.0, -1.0) * texel).rgb);
+    let luma_sw = luma(textureSample(t_input, s_input, uv + vec2<f32>(-1.0, 1.0) * texel).rgb);
+    let luma_se = luma(textureSample(t_input, s_input, uv + vec2<f32>(1.0, 1.0) * texel).rgb);
+    let luma_m = luma(color_m.rgb);
+
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    var dir = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * settings.reduce_mul, settings.reduce_min);
+    let inverse_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * inverse_dir_min, vec2<f32>(-settings.span_max), vec2<f32>(settings.span_max)) * texel;
+
+    let color_a = 0.5 * (
+        textureSample(t_input, s_input, uv + dir * (1.0 / 3.0 - 0.5)).rgb +
+        textureSample(t_input, s_input, uv + dir * (2.0 / 3.0 - 0.5)).rgb
+    );
+    let color_b = color_a * 0.5 + 0.25 * (
+        textureSample(t_input, s_input, uv + dir * -0.5).rgb +
+        textureSample(t_input, s_input, uv + dir * 0.5).rgb
+    );
+
+    let luma_b = luma(color_b);
+    if luma_b < luma_min || luma_b > luma_max {
+        return vec4<f32>(color_a, color_m.a);
+    }
+    return vec4<f32>(color_b, color_m.a);
+}
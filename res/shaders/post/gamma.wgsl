@@ -0,0 +1,12 @@
+struct GammaSettings {
+    gamma: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> settings: GammaSettings;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, in.tex_coords);
+    return vec4<f32>(pow(color.rgb, vec3<f32>(1.0 / settings.gamma)), color.a);
+}
@@ -0,0 +1,79 @@
+// Auto exposure, builds a histogram of the scene's log luminance and averages it into an exposure value
+
+struct HistogramSettings {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    // Fraction of the way to the new average covered each frame
+    adaptation: f32,
+    compensation: f32,
+    pixel_count: u32,
+}
+
+struct Exposure {
+    average_luminance: f32,
+    exposure: f32,
+}
+
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var<storage, read_write> histogram: array<atomic<u32>, 256>;
+@group(0) @binding(2)
+var<storage, read_write> exposure: Exposure;
+@group(0) @binding(3)
+var<uniform> settings: HistogramSettings;
+
+var<workgroup> local_bins: array<atomic<u32>, 256>;
+var<workgroup> weighted: array<f32, 256>;
+
+// Bin 0 holds black pixels, the remaining bins cover the luminance range logarithmically
+fn bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    if luminance < 0.0001 {
+        return 0u;
+    }
+    let position = saturate((log2(luminance) - settings.min_log_luminance) / settings.log_luminance_range);
+    return u32(position * 254.0 + 1.0);
+}
+
+@compute @workgroup_size(16, 16)
+fn cs_histogram(@builtin(global_invocation_id) id: vec3<u32>, @builtin(local_invocation_index) index: u32) {
+    atomicStore(&local_bins[index], 0u);
+    workgroupBarrier();
+
+    let size = vec2<u32>(textureDimensions(t_scene));
+    if id.x < size.x && id.y < size.y {
+        let color = textureLoad(t_scene, vec2<i32>(id.xy), 0).rgb;
+        atomicAdd(&local_bins[bin(color)], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[index], atomicLoad(&local_bins[index]));
+}
+
+@compute @workgroup_size(256)
+fn cs_average(@builtin(local_invocation_index) index: u32) {
+    let count = atomicLoad(&histogram[index]);
+    weighted[index] = f32(count) * f32(index);
+    // Clear the histogram for the next frame
+    atomicStore(&histogram[index], 0u);
+    workgroupBarrier();
+
+    for (var stride = 128u; stride > 0u; stride = stride >> 1u) {
+        if index < stride {
+            weighted[index] = weighted[index] + weighted[index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if index == 0u {
+        // `count` is the number of black pixels here, they don't count towards the average
+        let weighted_bin = weighted[0] / max(f32(settings.pixel_count) - f32(count), 1.0) - 1.0;
+        let log_average = weighted_bin / 254.0 * settings.log_luminance_range + settings.min_log_luminance;
+        let average = exp2(log_average);
+
+        let adapted = exposure.average_luminance + (average - exposure.average_luminance) * settings.adaptation;
+        exposure.average_luminance = adapted;
+        exposure.exposure = settings.compensation * 0.18 / max(adapted, 0.0001);
+    }
+}
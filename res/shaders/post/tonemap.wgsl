@@ -0,0 +1,60 @@
+struct TonemapSettings {
+    curve: u32,
+}
+
+// Written by the auto exposure passes or set directly for manual exposure
+struct Exposure {
+    average_luminance: f32,
+    exposure: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> settings: TonemapSettings;
+@group(2) @binding(0)
+var<storage, read> exposure: Exposure;
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (color + vec3<f32>(1.0));
+}
+
+// Curve fit of the ACES reference transform by Krzysztof Narkowicz
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return saturate((color * (a * color + b)) / (color * (c * color + d) + e));
+}
+
+// Filmic curve by John Hable
+fn hable(color: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return ((color * (a * color + c * b) + d * e) / (color * (a * color + b) + d * f)) - e / f;
+}
+
+fn filmic(color: vec3<f32>) -> vec3<f32> {
+    let white = 11.2;
+    return hable(color * 2.0) / hable(vec3<f32>(white));
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, in.tex_coords);
+    let exposed = color.rgb * exposure.exposure;
+
+    var mapped: vec3<f32>;
+    if settings.curve == 1u {
+        mapped = aces(exposed);
+    } else if settings.curve == 2u {
+        mapped = filmic(exposed);
+    } else {
+        mapped = reinhard(exposed);
+    }
+    return vec4<f32>(mapped, color.a);
+}
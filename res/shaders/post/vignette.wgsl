@@ -0,0 +1,16 @@
+struct VignetteSettings {
+    intensity: f32,
+    radius: f32,
+    smoothness: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> settings: VignetteSettings;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, in.tex_coords);
+    let distance = distance(in.tex_coords, vec2<f32>(0.5));
+    let vignette = smoothstep(settings.radius, settings.radius - settings.smoothness, distance);
+    return vec4<f32>(color.rgb * mix(1.0, vignette, settings.intensity), color.a);
+}
@@ -1,9 +1,5 @@
 // Vertex shader
-struct CameraUniform {
-    view_proj: mat4x4<f32>,
-};
-@group(1) @binding(0) // 1.
-var<uniform> camera: CameraUniform;
+#include "common/camera.wgsl"
 
 struct InstanceInput {
     @location(5) model_matrix_0: vec4<f32>,
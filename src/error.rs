@@ -0,0 +1,95 @@
+use std::fmt::{Display, Formatter};
+
+/// Where and why a shader was rejected, with the location mapped back to the file the code came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderDiagnostic {
+    pub file: String,
+    /// 1-based, 0 if the compiler didn't point at the code.
+    pub line: u32,
+    pub column: u32,
+    /// The offending line with the code in question underlined, empty without a location.
+    pub snippet: String,
+    pub message: String,
+}
+
+impl Display for ShaderDiagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)?;
+        if !self.snippet.is_empty() {
+            write!(f, "\n{}", self.snippet)?;
+        }
+        Ok(())
+    }
+}
+
+/// Errors that can occur while setting up or running the engine.
+#[derive(Debug)]
+pub enum HermitError {
+    AdapterNotFound,
+    DeviceRequestFailed(wgpu::RequestDeviceError),
+    FeatureNotSupported(wgpu::Features),
+    ShaderLoadFailed { path: String, message: String },
+    ShaderCompileFailed { path: String, message: String },
+    ShaderPreprocessFailed { path: String, line: u32, message: String },
+    AssetNotFound(String),
+    AssetLoadFailed { name: String, message: String },
+    SurfaceFailed(String),
+    WindowCreationFailed(winit::error::OsError),
+    RenderGraphInvalid(String),
+    CaptureFailed(String),
+    PipelineBuildFailed { label: String, message: String },
+    ShaderMismatch { shader: String, message: String },
+    ShaderInvalid(ShaderDiagnostic),
+}
+
+impl Display for HermitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HermitError::AdapterNotFound => write!(f, "No suitable graphics adapter found"),
+            HermitError::DeviceRequestFailed(error) => write!(f, "Failed to request device: {}", error),
+            HermitError::FeatureNotSupported(features) => write!(f, "Required features are not supported: {:?}", features),
+            HermitError::ShaderLoadFailed { path, message } => write!(f, "Cannot open shader {}: {}", path, message),
+            HermitError::ShaderCompileFailed { path, message } => write!(f, "Cannot compile shader {}: {}", path, message),
+            HermitError::ShaderPreprocessFailed { path, line, message } => write!(f, "Cannot preprocess shader {}:{}: {}", path, line, message),
+            HermitError::AssetNotFound(path) => write!(f, "Asset not found: {}", path),
+            HermitError::AssetLoadFailed { name, message } => write!(f, "Cannot load asset {}: {}", name, message),
+            HermitError::SurfaceFailed(message) => write!(f, "Surface error: {}", message),
+            HermitError::WindowCreationFailed(error) => write!(f, "Failed to create window: {}", error),
+            HermitError::RenderGraphInvalid(message) => write!(f, "Invalid render graph: {}", message),
+            HermitError::CaptureFailed(message) => write!(f, "Failed to capture frame: {}", message),
+            HermitError::PipelineBuildFailed { label, message } => write!(f, "Cannot build pipeline {}: {}", label, message),
+            HermitError::ShaderMismatch { shader, message } => write!(f, "Shader {} doesn't match the pipeline: {}", shader, message),
+            HermitError::ShaderInvalid(diagnostic) => write!(f, "Invalid shader {}", diagnostic),
+        }
+    }
+}
+
+impl std::error::Error for HermitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HermitError::DeviceRequestFailed(error) => Some(error),
+            HermitError::WindowCreationFailed(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<wgpu::RequestDeviceError> for HermitError {
+    fn from(error: wgpu::RequestDeviceError) -> Self {
+        HermitError::DeviceRequestFailed(error)
+    }
+}
+
+impl From<wgpu::SurfaceError> for HermitError {
+    fn from(error: wgpu::SurfaceError) -> Self {
+        HermitError::SurfaceFailed(error.to_string())
+    }
+}
+
+impl From<winit::error::OsError> for HermitError {
+    fn from(error: winit::error::OsError) -> Self {
+        HermitError::WindowCreationFailed(error)
+    }
+}
+
+pub type HermitResult<T> = Result<T, HermitError>;
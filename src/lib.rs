@@ -1,14 +1,13 @@
 mod window;
+pub mod error;
 mod camera;
-mod rendering;
-mod util;
-mod shape;
+pub mod rendering;
+pub mod util;
+pub mod shape;
 
 use std::fs::remove_dir;
-use std::iter;
 use cgmath::{InnerSpace, Rotation3, Zero};
-use wgpu::{BindGroup, BindGroupLayout, BindGroupLayoutEntry, BindingType, BufferBindingType, BufferUsages, Color, ShaderStages, VertexBufferLayout};
-use wgpu::BindingResource::{Sampler, TextureView};
+use wgpu::{BindGroup, BindGroupLayout, BindGroupLayoutEntry, BufferBindingType, BufferUsages, Color, ShaderStages, VertexBufferLayout};
 use wgpu::IndexFormat::Uint16;
 
 use winit::{
@@ -20,27 +19,93 @@ use winit::dpi::PhysicalSize;
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
-use rendering::bind_group::{BindGroupBuilder, GroupEntry, LayoutEntry};
+use rendering::bind_group::BindGroupBuilder;
 use camera::camera_controller::CameraController;
 use crate::camera::camera::{Camera, CameraUniform};
+use crate::error::{HermitError, HermitResult};
 use crate::rendering::bind_group;
 use crate::rendering::buffer::BufferBuilder;
-use crate::rendering::canvas::Canvas;
+use crate::rendering::canvas::{Canvas, CanvasOptions};
 use crate::rendering::instance::{Instance, InstanceRaw, NUM_INSTANCES_PER_ROW};
 use crate::rendering::model::{DrawModel, Material, Mesh, Model, ModelVertex};
 use crate::rendering::pipeline::Pipeline;
+use crate::rendering::post_effects::Vignette;
+use crate::rendering::post_process::PostStack;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::rendering::hot_reload::ShaderWatcher;
+use crate::rendering::profiler::Profiler;
+use crate::rendering::render_stats::{RenderStats, StatsRenderPass};
+use crate::rendering::render_graph::{RenderGraph, ResourceId};
+use crate::rendering::recorder::Recorder;
+use crate::rendering::resource_manager::ResourceManager;
+use crate::rendering::screenshot::Screenshot;
 use crate::rendering::shader::{FragmentEntry, Shader, VertexEntry};
 use crate::shape::shape_drawer::{Vertex, Polygon, Rectangle, Shape, ShapeDrawer, Triangle, ShapeData};
+use crate::util::clock::Clock;
 use crate::util::resources;
 use crate::util::textures::Texture;
 use crate::window::{HermitWindow, WindowData};
 
-struct Engine<'a> {
-    canvas: Canvas,
-
+struct Scene<'a> {
     polygon: Polygon<'a>,
     rectangle: Rectangle<'a>,
     triangle: Triangle<'a>,
+    post: PostStack,
+    color_shader: Shader,
+    texture_shader: Shader,
+    resources: ResourceManager,
+}
+
+impl Scene<'_> {
+    /// Rebuilds everything built from one of the `changed` shaders, logging shaders that fail to compile.
+    fn reload(&mut self, canvas: &Canvas, changed: &[String]) {
+        if self.color_shader.depends_on(changed) {
+            if let Some(shader) = Self::reload_shader(COLOR_SHADER, &self.color_shader, canvas) {
+                for shape in [&mut self.polygon as &mut dyn Shape, &mut self.triangle] {
+                    if let Err(error) = shape.rebuild_pipeline(&shader, canvas) {
+                        log::error!("{}", error);
+                    }
+                }
+                self.color_shader = shader;
+            }
+        }
+        if self.texture_shader.depends_on(changed) {
+            if let Some(shader) = Self::reload_shader(TEXTURE_SHADER, &self.texture_shader, canvas) {
+                if let Err(error) = self.rectangle.rebuild_pipeline(&shader, canvas) {
+                    log::error!("{}", error);
+                }
+                self.texture_shader = shader;
+            }
+        }
+
+        self.post.reload(canvas, changed);
+    }
+
+    fn reload_shader(path: &str, old: &Shader, canvas: &Canvas) -> Option<Shader> {
+        match pollster::block_on(Shader::new(path, canvas)) {
+            Ok(shader) => {
+                canvas.pipeline_cache.remove_shader(old.id());
+                Some(shader)
+            }
+            Err(error) => {
+                log::error!("{}", error);
+                None
+            }
+        }
+    }
+}
+
+struct Engine<'a> {
+    canvas: Canvas,
+    graph: RenderGraph<Scene<'a>>,
+    scene: Scene<'a>,
+    screenshot: Screenshot,
+    recorder: Recorder,
+    clock: Clock,
+    profiler: Profiler,
+    // Only set in debug builds
+    #[cfg(not(target_arch = "wasm32"))]
+    shader_watcher: Option<ShaderWatcher>,
 
     /*
     /*
@@ -50,49 +115,47 @@ struct Engine<'a> {
     camera: Camera,
     camera_controller: CameraController,
 
-    camera_uniform: CameraUniform,
-    camera_buffer: wgpu::Buffer,
-    camera_bind_group: wgpu::BindGroup,
+    camera_uniform: UniformBuffer<CameraUniform>,
 
     instances: Vec<Instance>,
     instance_buffer: wgpu::Buffer,
 
-    depth_texture: Texture,
-
     obj_model: Model,
      */
 }
 
 impl<'a> Engine<'a> {
-    async fn new(window: &Window) -> Engine<'a> {
-        let canvas = Canvas::new(window).await;
+    async fn new(window: &Window, options: &CanvasOptions) -> HermitResult<Engine<'a>> {
+        let canvas = Canvas::new(window, options).await?;
 
-        let shader = Shader::new("shaders/color.wgsl", &canvas).await;
-        let shader2 = Shader::new("shaders/texture.wgsl", &canvas).await;
+        // Created first so every shader is loaded from the source directory it watches
+        #[cfg(not(target_arch = "wasm32"))]
+        let shader_watcher = cfg!(debug_assertions).then(|| {
+            let watcher = ShaderWatcher::new();
+            resources::set_resource_root(Some(watcher.root().to_path_buf()));
+            watcher
+        });
 
-        let diffuse_bytes = include_bytes!("../res/cube-diffuse.jpg");
-        let diffuse_texture = Texture::from_bytes(&canvas.device, &canvas.queue, diffuse_bytes, "cube-diffuse").unwrap();
-        let (dbgl, diffuse_bind_group) = BindGroupBuilder::new(&canvas,
-            &[
-                    LayoutEntry::new(0, ShaderStages::FRAGMENT, BindingType::Texture {
-                        multisampled: false,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                    }),
-                    LayoutEntry::new(1, ShaderStages::FRAGMENT, BindingType::Sampler(wgpu::SamplerBindingType::Filtering)),
-                ],
-            &[
-                GroupEntry::new_binding_resource(0, TextureView(&diffuse_texture.view)),
-                GroupEntry::new_binding_resource(1, Sampler(&diffuse_texture.sampler)),
-            ],
-            None,
-            true
-        );
-        let dbg = diffuse_bind_group.unwrap();
+        let shader = Shader::new(COLOR_SHADER, &canvas).await?;
+        let shader2 = Shader::new(TEXTURE_SHADER, &canvas).await?;
 
-        let polygon = Polygon::new(&shader, VERTICES, INDICES, None, &canvas);
-        let rectangle = Rectangle::new(&shader2, VERTICES2, Some((dbgl,dbg)), &canvas);
-        let triangle = Triangle::new(&shader,VERTICES3, None, &canvas);
+        let mut resources = ResourceManager::new();
+        let diffuse_bytes = include_bytes!("../res/cube-diffuse.jpg");
+        let diffuse_texture = Texture::from_bytes(&canvas.device, &canvas.queue, diffuse_bytes, "cube-diffuse")
+            .map_err(|error| HermitError::AssetLoadFailed { name: "cube-diffuse".to_string(), message: error.to_string() })?;
+        let diffuse_texture = resources.textures.insert_named("cube-diffuse.jpg", diffuse_texture);
+        let diffuse_texture = resources.textures.get(diffuse_texture).unwrap();
+        let (dbgl, dbg) = BindGroupBuilder::new(&canvas)
+            .texture_2d(ShaderStages::FRAGMENT, &diffuse_texture.view)
+            .sampler(ShaderStages::FRAGMENT, &diffuse_texture.sampler)
+            .build();
+
+        let polygon = Polygon::new(&shader, VERTICES, INDICES, None, &canvas)?;
+        let rectangle = Rectangle::new(&shader2, VERTICES2, Some((dbgl, dbg)), &canvas)?;
+        let triangle = Triangle::new(&shader,VERTICES3, None, &canvas)?;
+
+        let post = PostStack::new(&canvas).await?
+            .with_effect(Vignette::new(&canvas).await?.with_intensity(0.5));
 
         /*
         let shader = Shader::new("shaders/shader.wgsl", &canvas).await;
@@ -113,22 +176,9 @@ impl<'a> Engine<'a> {
             zfar: 100.0,
         };
 
-        let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_proj(&camera);
-        let camera_buffer = BufferBuilder::new(&[camera_uniform], BufferUsages::UNIFORM | BufferUsages::COPY_DST, Some("Camera Buffer"), &canvas);
-
-        let (camera_bind_group_layout, camera_bind_group) = BindGroupBuilder::new(
-            &canvas,
-            &[LayoutEntry::new(0, ShaderStages::VERTEX, BindingType::Buffer {
-                ty: BufferBindingType::Uniform,
-                has_dynamic_offset: false,
-                min_binding_size: None,
-            })],
-            &[GroupEntry::new(0, &camera_buffer)],
-            Some("Camera bind group"),
-            true,
-        );
-        let camera_bind_group = camera_bind_group.unwrap();
+        let mut uniform = CameraUniform::new();
+        uniform.update_view_proj(&camera);
+        let camera_uniform = UniformBuffer::new(&canvas, uniform, ShaderStages::VERTEX, Some("Camera Buffer"));
 
         let camera_controller = CameraController::new(0.2);
 
@@ -156,10 +206,8 @@ impl<'a> Engine<'a> {
         let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
         let instance_buffer = BufferBuilder::new(&instance_data, BufferUsages::VERTEX, Some("Instance Buffer"), &canvas);
 
-        let depth_texture = Texture::create_depth_texture(&canvas.device, &canvas.config, "depth_texture");
-
 
-        let (texture_bind_group_layout, _) = BindGroupBuilder::new(
+        let texture_bind_group_layout = BindLayout::new(
             &canvas,
             &[
                 LayoutEntry::new(0, ShaderStages::FRAGMENT, BindingType::Texture {
@@ -169,34 +217,96 @@ impl<'a> Engine<'a> {
                 }),
                 LayoutEntry::new(1, ShaderStages::FRAGMENT, BindingType::Sampler(wgpu::SamplerBindingType::Filtering)),
             ],
-            &[],
             Some("Texture Bind Group"),
-            false,
         );
 
-        let obj_model = resources::load_model(
+        let obj_model = resources.load_model(
             "cube.obj",
-            &canvas.device,
-            &canvas.queue,
+            &canvas,
             &texture_bind_group_layout,
         ).await.unwrap();
 
         let render_pipeline = Pipeline::new(
             &canvas,
-            &[&texture_bind_group_layout, &camera_bind_group_layout],
+            &[&texture_bind_group_layout.layout, &camera_uniform.layout],
             Some("Render Pipeline"),
             VertexEntry::new(&shader.shader_mod, "vs_main", &[ModelVertex::desc(), InstanceRaw::desc()]),
             FragmentEntry::new(&shader.shader_mod, "fs_main"),
+            true,
         );
 
-        let square = Square::new(&canvas, &camera_bind_group_layout).await;
+        let square = Square::new(&canvas, &camera_uniform.layout).await;
          */
 
-        Self {
+        let mut graph = RenderGraph::new();
+        let scene_target = graph.import("scene target");
+        graph.add_pass_fn("Scene", &[], &[scene_target], |context, scene: &Scene| {
+            let mut render_pass = StatsRenderPass::new(
+                scene.post.scene_target().begin_render_pass(context.encoder, CLEAR_COLOR),
+                &context.canvas.stats,
+            );
+
+            //render_pass.set_pipeline(&self.render_pipeline);
+
+            //render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            //render_pass.set_index_buffer(self.index_buffer.slice(..), Uint16);
+            //render_pass.draw_indexed(0..self.num_indices, 0,0..1);
+
+            let mut drawer = ShapeDrawer::new(&mut render_pass);
+            drawer.draw_shape(&scene.polygon);
+            drawer.draw_shape(&scene.rectangle);
+            drawer.draw_shape(&scene.triangle);
+
+            // NEW!
+            /*
+            render_pass.set_pipeline(&self.render_pipeline);
+
+            render_pass.set_bind_group(0, &self.camera_uniform.group, &[]);
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+
+            // UPDATED!
+            render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as _);
+             */
+            /*render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+
+            render_pass.set_pipeline(&self.render_pipeline);
+
+            let mesh = scene.resources.meshes.get(self.obj_model.meshes[0]).unwrap();
+            let material = scene.resources.materials.get(mesh.material).unwrap();
+
+
+            render_pass.draw_mesh_instanced(mesh, material, 0..self.instances.len() as u32, &self.camera_uniform.group);
+             */
+        });
+        graph.add_pass_fn("Post", &[scene_target], &[ResourceId::SURFACE], |context, scene: &Scene| {
+            scene.post.apply(context.canvas, context.encoder, &context.frame.view);
+        });
+        graph.compile()?;
+
+        let screenshot = Screenshot::new(&canvas).await?;
+        let recorder = Recorder::new(&canvas).await?;
+        let profiler = Profiler::new(&canvas);
+
+        Ok(Self {
             canvas,
-            polygon,
-            rectangle,
-            triangle,
+            graph,
+            scene: Scene {
+                polygon,
+                rectangle,
+                triangle,
+                post,
+                color_shader: shader,
+                texture_shader: shader2,
+                resources,
+            },
+            screenshot,
+            recorder,
+            clock: Clock::new(),
+            profiler,
+            #[cfg(not(target_arch = "wasm32"))]
+            shader_watcher,
             /*
             /*
              */
@@ -205,129 +315,111 @@ impl<'a> Engine<'a> {
             camera,
             camera_controller,
             camera_uniform,
-            camera_buffer,
-            camera_bind_group,
             instances,
             instance_buffer,
-            depth_texture,
             obj_model,
              */
-        }
+        })
     }
 
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
-            self.canvas.size = new_size;
-            self.canvas.config.width = new_size.width;
-            self.canvas.config.height = new_size.height;
-            self.canvas.surface.configure(&self.canvas.device, &self.canvas.config);
-        }
-       // self.depth_texture = Texture::create_depth_texture(&self.canvas.device, &self.canvas.config, "depth_texture");
+        self.canvas.resize(new_size);
+        self.scene.post.resize(&self.canvas);
     }
 
-    #[allow(unused_variables)]
     fn input(&mut self, event: &WindowEvent) -> bool {
         //self.camera_controller.process_events(event)
-        false
+        self.screenshot.handle_input(event) || self.recorder.handle_input(event)
     }
 
     fn update(&mut self) {
-        //self.camera_controller.update_camera(&mut self.camera);
-        //self.camera_uniform.update_view_proj(&self.camera);
-        //self.canvas.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
-    }
-
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.canvas.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-
-        let mut encoder = self
-            .canvas.device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
-
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[
-                    // This is what @location(0) in the fragment shader targets
-                    Some(wgpu::RenderPassColorAttachment {
-                        view: &view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(
-                                wgpu::Color {
-                                    r: 0.1,
-                                    g: 0.2,
-                                    b: 0.3,
-                                    a: 1.0,
-                                }
-                            ),
-                            store: true,
-                        },
-                    })
-                ],
-                depth_stencil_attachment: /*Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                })*/ None,
-            });
-
-            //render_pass.set_pipeline(&self.render_pipeline);
-
-            //render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            //render_pass.set_index_buffer(self.index_buffer.slice(..), Uint16);
-            //render_pass.draw_indexed(0..self.num_indices, 0,0..1);
-
-            let mut drawer = ShapeDrawer::new(&mut render_pass);
-            drawer.draw_shape(&self.polygon);
-            drawer.draw_shape(&self.rectangle);
-            drawer.draw_shape(&self.triangle);
-
-            // NEW!
-            /*
-            render_pass.set_pipeline(&self.render_pipeline);
+        self.profiler.begin_frame(&self.canvas);
+        self.profiler.begin_scope("update");
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(watcher) = &mut self.shader_watcher {
+            let changed = watcher.poll();
+            if !changed.is_empty() {
+                self.scene.reload(&self.canvas, &changed);
+            }
+        }
 
-            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        // Recording advances time by a fixed step per frame, however long rendering takes
+        self.clock.tick(self.recorder.timestep());
 
-            // UPDATED!
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as _);
-             */
-            /*render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        //self.camera_controller.update_camera(&mut self.camera);
+        //self.camera_uniform.update(|uniform| uniform.update_view_proj(&self.camera));
+        //self.camera_uniform.flush(&self.canvas);
 
-            render_pass.set_pipeline(&self.render_pipeline);
+        self.profiler.end_scope();
+    }
 
-            let mesh = &self.obj_model.meshes[0];
-            let material = &self.obj_model.materials[mesh.material];
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        self.profiler.begin_scope("render");
+        let result = self.render_frame();
+        self.profiler.end_scope();
+
+        if self.profiler.frame().is_multiple_of(PROFILER_LOG_INTERVAL) {
+            log::debug!("{}", self.profiler.summary());
+            log::debug!("{:?}", self.render_stats());
+            log::debug!("{:?}", self.canvas.uploader.last_frame());
+        }
+        result
+    }
 
+    fn render_frame(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let mut frame = self.canvas.frame()?;
+        // Both redirect the frame, so they have to finish in reverse order
+        self.screenshot.redirect(&self.canvas, &mut frame);
+        self.recorder.redirect(&self.canvas, &mut frame);
 
-            render_pass.draw_mesh_instanced(mesh, material, 0..self.instances.len() as u32, &self.camera_bind_group);
-             */
+        if let Err(error) = self.graph.execute_profiled(&self.canvas, &frame, &self.scene, &mut self.profiler) {
+            log::error!("{}", error);
         }
-
-        self.canvas.queue.submit(iter::once(encoder.finish()));
-        output.present();
+        if let Err(error) = self.recorder.finish(&self.canvas, &mut frame) {
+            log::error!("{}", error);
+        }
+        if let Err(error) = self.screenshot.finish(&self.canvas, &mut frame) {
+            log::error!("{}", error);
+        }
+        frame.present();
+        self.canvas.stats.end_frame();
+        self.scene.resources.end_frame();
 
         Ok(())
     }
+
+    /// Counters of the last rendered frame.
+    fn render_stats(&self) -> RenderStats {
+        self.canvas.stats.last_frame()
+    }
 }
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub async fn run() {
-    let (event_loop, window) = HermitWindow::new(WindowData::new(true, "HERMIT ENGINE".to_string(), PhysicalSize::new(800, 800))).await;
+    // Only used by the profiler if the adapter supports it
+    let canvas_options = CanvasOptions::default()
+        .with_sample_count(4)
+        .with_optional_features(wgpu::Features::TIMESTAMP_QUERY);
+    let window_data = WindowData::new(true, "HERMIT ENGINE".to_string(), PhysicalSize::new(800, 800))
+        .with_canvas_options(canvas_options.clone());
+
+    let (event_loop, window) = match HermitWindow::new(window_data).await {
+        Ok(result) => result,
+        Err(error) => {
+            log::error!("{}", error);
+            return;
+        }
+    };
 
     // State::new uses async code, so we're going to wait for it to finish
-    let mut engine = Engine::new(&window).await;
+    let mut engine = match Engine::new(&window, &canvas_options).await {
+        Ok(engine) => engine,
+        Err(error) => {
+            log::error!("{}", error);
+            return;
+        }
+    };
 
     event_loop.run(move |event, _, control_flow| {
         match event {
@@ -381,6 +473,19 @@ pub async fn run() {
     });
 }
 
+const COLOR_SHADER: &str = "shaders/color.wgsl";
+const TEXTURE_SHADER: &str = "shaders/texture.wgsl";
+
+// Frames between profiler summaries in the debug log
+const PROFILER_LOG_INTERVAL: u64 = 300;
+
+const CLEAR_COLOR: wgpu::Color = wgpu::Color {
+    r: 0.1,
+    g: 0.2,
+    b: 0.3,
+    a: 1.0,
+};
+
 const VERTICES: &[Vertex] = &[
     Vertex { position: [-0.0868241, 0.49240386, 0.0], color_or_coord: [1.0, 0.0, 0.0] }, // A
     Vertex { position: [-0.49513406, 0.06958647, 0.0], color_or_coord: [0.0, 1.0, 0.0] }, // B
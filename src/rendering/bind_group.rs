@@ -1,29 +1,159 @@
-use wgpu::{BindGroup, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, ShaderStages};
+use bytemuck::Pod;
+use wgpu::{BindGroup, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, Sampler, ShaderStages, StorageTextureAccess, TextureFormat, TextureView};
+use crate::rendering::buffer::UniformBuffer;
 use crate::rendering::canvas::Canvas;
 
-pub struct BindGroupBuilder {
+/// Builds a bind group together with its layout, one call per binding so the two can't disagree:
+///
+/// `BindGroupBuilder::new(canvas).uniform::<Camera>(ShaderStages::VERTEX, &buffer).texture_2d(ShaderStages::FRAGMENT, &view).sampler(ShaderStages::FRAGMENT, &sampler).build()`
+///
+/// Bindings are numbered in the order they're added, starting at 0. Use [`BindLayout::group`] for more
+/// groups with the same layout.
+pub struct BindGroupBuilder<'a> {
+    canvas: &'a Canvas,
+    label: Option<&'a str>,
+    layout_entries: Vec<BindGroupLayoutEntry>,
+    resources: Vec<BindingResource<'a>>,
+}
+
+impl<'a> BindGroupBuilder<'a> {
+    pub fn new(canvas: &'a Canvas) -> Self {
+        Self { canvas, label: None, layout_entries: vec![], resources: vec![] }
+    }
+
+    /// Used for the layout and the group.
+    pub fn label(mut self, label: Option<&'a str>) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// Uniform buffer holding a `T`, padded like [`UniformBuffer`] pads it.
+    pub fn uniform<T: Pod>(self, visibility: ShaderStages, buffer: &'a Buffer) -> Self {
+        let binding = self.next_binding();
+        self.entry(UniformBuffer::<T>::layout_entry(binding, visibility), buffer.as_entire_binding())
+    }
+
+    pub fn storage_buffer(self, visibility: ShaderStages, read_only: bool, buffer: &'a Buffer) -> Self {
+        let binding = self.next_binding();
+        self.entry(LayoutEntry::storage_buffer(binding, visibility, read_only), buffer.as_entire_binding())
+    }
+
+    /// Filterable 2d float texture, the kind [`BindGroupBuilder::sampler`] samples.
+    pub fn texture_2d(self, visibility: ShaderStages, view: &'a TextureView) -> Self {
+        let binding = self.next_binding();
+        self.entry(LayoutEntry::new(binding, visibility, BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        }), BindingResource::TextureView(view))
+    }
+
+    pub fn sampler(self, visibility: ShaderStages, sampler: &'a Sampler) -> Self {
+        let binding = self.next_binding();
+        self.entry(LayoutEntry::new(binding, visibility, BindingType::Sampler(wgpu::SamplerBindingType::Filtering)),
+            BindingResource::Sampler(sampler))
+    }
+
+    pub fn storage_texture(self, visibility: ShaderStages, format: TextureFormat, access: StorageTextureAccess, view: &'a TextureView) -> Self {
+        let binding = self.next_binding();
+        self.entry(LayoutEntry::storage_texture(binding, visibility, format, access), BindingResource::TextureView(view))
+    }
+
+    /// Any other binding. Panics if `entry.binding` isn't [`BindGroupBuilder::next_binding`], resources are
+    /// matched to bindings by position so they have to be added in order.
+    pub fn entry(mut self, entry: BindGroupLayoutEntry, resource: BindingResource<'a>) -> Self {
+        assert_eq!(entry.binding, self.next_binding(), "Bind group {:?} got binding {} out of order", self.label, entry.binding);
+        self.layout_entries.push(entry);
+        self.resources.push(resource);
+        self
+    }
+
+    /// Binding the next entry gets.
+    pub fn next_binding(&self) -> u32 {
+        self.layout_entries.len() as u32
+    }
 
+    /// Panics if a resource doesn't fit its binding.
+    pub fn build(self) -> (BindLayout, BindGroup) {
+        let layout = BindLayout::new(self.canvas, &self.layout_entries, self.label);
+        let group = layout.group(self.canvas, &self.resources);
+        (layout, group)
+    }
 }
 
-impl BindGroupBuilder {
-    pub fn new(canvas: &Canvas, layout_entries: &[BindGroupLayoutEntry], group_entries: &[BindGroupEntry], label: Option<&str>, group: bool) -> (BindGroupLayout, Option<BindGroup>) {
-        let group_layout = canvas.device.create_bind_group_layout(
-          &wgpu::BindGroupLayoutDescriptor {
-              entries: layout_entries,
-              label,
-          }
-        );
+/// A bind group layout along with the entries it was created from, which every group created from it is checked against.
+pub struct BindLayout {
+    pub layout: BindGroupLayout,
+    entries: Vec<BindGroupLayoutEntry>,
+    label: Option<String>,
+}
 
-        if !group { return (group_layout, None); }
+impl BindLayout {
+    /// Panics if the bindings of `entries` aren't numbered 0, 1, 2... in any order.
+    pub fn new(canvas: &Canvas, entries: &[BindGroupLayoutEntry], label: Option<&str>) -> Self {
+        let mut bindings: Vec<u32> = entries.iter().map(|entry| entry.binding).collect();
+        bindings.sort_unstable();
+        for (expected, binding) in bindings.iter().enumerate() {
+            assert!(*binding >= expected as u32, "Bind group {:?} has binding {} more than once", label, binding);
+            assert_eq!(*binding, expected as u32, "Bind group {:?} skips binding {}", label, expected);
+        }
 
-        let group = canvas.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &group_layout,
-            entries: group_entries,
+        let mut entries = entries.to_vec();
+        entries.sort_unstable_by_key(|entry| entry.binding);
+        let layout = canvas.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
             label,
         });
 
-        (group_layout, Some(group))
+        Self { layout, entries, label: label.map(str::to_string) }
+    }
+
+    /// A filterable 2d texture at binding 0 and its sampler at binding 1, both for fragment shaders.
+    /// What textured shapes and post effects expect their textures in.
+    pub fn texture_2d(canvas: &Canvas, label: Option<&str>) -> Self {
+        Self::new(canvas, &[
+            LayoutEntry::new(0, ShaderStages::FRAGMENT, BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            }),
+            LayoutEntry::new(1, ShaderStages::FRAGMENT, BindingType::Sampler(wgpu::SamplerBindingType::Filtering)),
+        ], label)
     }
+
+    /// Sorted by binding.
+    pub fn entries(&self) -> &[BindGroupLayoutEntry] {
+        &self.entries
+    }
+
+    /// A group with `resources` at bindings 0, 1, 2... in order.
+    /// Panics if there isn't one resource per binding or a resource doesn't fit its binding,
+    /// e.g. a sampler where the layout has a buffer.
+    pub fn group(&self, canvas: &Canvas, resources: &[BindingResource]) -> BindGroup {
+        assert_eq!(resources.len(), self.entries.len(),
+            "Bind group {:?} has {} bindings but got {} resources", self.label, self.entries.len(), resources.len());
+
+        let group_entries: Vec<BindGroupEntry> = self.entries.iter().zip(resources)
+            .map(|(entry, resource)| {
+                assert!(resource_fits(&entry.ty, resource),
+                    "Bind group {:?} binding {} expects {:?} but got {:?}", self.label, entry.binding, entry.ty, resource);
+                GroupEntry::new_binding_resource(entry.binding, resource.clone())
+            })
+            .collect();
+
+        canvas.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.layout,
+            entries: &group_entries,
+            label: self.label.as_deref(),
+        })
+    }
+}
+
+fn resource_fits(ty: &BindingType, resource: &BindingResource) -> bool {
+    matches!((ty, resource),
+        (BindingType::Buffer { .. }, BindingResource::Buffer(_) | BindingResource::BufferArray(_))
+        | (BindingType::Sampler(_), BindingResource::Sampler(_) | BindingResource::SamplerArray(_))
+        | (BindingType::Texture { .. } | BindingType::StorageTexture { .. }, BindingResource::TextureView(_) | BindingResource::TextureViewArray(_)))
 }
 
 pub struct LayoutEntry {
@@ -39,6 +169,25 @@ impl LayoutEntry {
             count: None,
         }
     }
+
+    /// Storage buffer, compute shaders can write to it unless it's `read_only`.
+    pub fn storage_buffer(binding: u32, visibility: ShaderStages, read_only: bool) -> BindGroupLayoutEntry {
+        Self::new(binding, visibility, BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        })
+    }
+
+    /// 2d texture accessed with `textureLoad`/`textureStore` instead of a sampler.
+    /// Only [`StorageTextureAccess::WriteOnly`] works without extra device features.
+    pub fn storage_texture(binding: u32, visibility: ShaderStages, format: TextureFormat, access: StorageTextureAccess) -> BindGroupLayoutEntry {
+        Self::new(binding, visibility, BindingType::StorageTexture {
+            access,
+            format,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        })
+    }
 }
 
 pub struct GroupEntry {
@@ -58,4 +207,9 @@ impl GroupEntry {
             resource,
         }
     }
+
+    /// Entry for a sampled or storage texture.
+    pub fn new_texture_view(binding: u32, view: &TextureView) -> BindGroupEntry<'_> {
+        Self::new_binding_resource(binding, BindingResource::TextureView(view))
+    }
 }
\ No newline at end of file
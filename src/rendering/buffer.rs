@@ -1,19 +1,290 @@
+use std::cell::Cell;
+use std::mem::size_of;
+use std::num::NonZeroU64;
+use std::ops::Range;
 use bytemuck::Pod;
-use wgpu::Buffer;
+use wgpu::{BindGroup, BindGroupLayout, BindGroupLayoutEntry, Buffer, BufferAddress, BufferSlice, BufferUsages, CommandEncoder, ShaderStages};
 use wgpu::util::DeviceExt;
+use crate::rendering::bind_group::{BindGroupBuilder, LayoutEntry};
 use crate::rendering::canvas::Canvas;
 
 pub struct BufferBuilder {}
 
 impl BufferBuilder {
     pub fn new<T: Pod>(content: &[T], usage: wgpu::BufferUsages, label: Option<&str>, canvas: &Canvas) -> Buffer {
+        let contents: &[u8] = bytemuck::cast_slice(content);
         let buffer = canvas.device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label,
-                contents: bytemuck::cast_slice(content),
+                contents,
                 usage,
             }
         );
+        canvas.stats.record_upload(contents.len() as u64);
         buffer
     }
-}
\ No newline at end of file
+}
+
+/// A uniform buffer holding a single `T` along with the layout and bind group that bind it at binding 0.
+/// [`UniformBuffer::set`] and [`UniformBuffer::update`] only change the copy on the cpu, [`UniformBuffer::flush`]
+/// uploads it if it changed, so it should be called once per frame before the frame is submitted.
+///
+/// `T` has to follow the WGSL uniform layout rules itself, e.g. a `vec3<f32>` is padded to 16 bytes.
+/// Its size has to be a multiple of 16 bytes, the size WGSL rounds uniform structs to, so the padding
+/// at the end needs explicit fields as well.
+pub struct UniformBuffer<T: Pod> {
+    value: T,
+    dirty: Cell<bool>,
+    size: u64,
+    pub buffer: Buffer,
+    pub layout: BindGroupLayout,
+    pub group: BindGroup,
+}
+
+impl<T: Pod> UniformBuffer<T> {
+    /// Alignment WGSL gives to structs in the uniform address space.
+    pub const ALIGNMENT: u64 = 16;
+
+    pub fn new(canvas: &Canvas, value: T, visibility: ShaderStages, label: Option<&str>) -> Self {
+        let size = Self::binding_size();
+        let max_size = canvas.device.limits().max_uniform_buffer_binding_size as u64;
+        assert!(size <= max_size, "Uniform buffer {:?} needs {} bytes but the device binds at most {}", label, size, max_size);
+
+        let buffer = BufferBuilder::new(bytemuck::bytes_of(&value), BufferUsages::UNIFORM | BufferUsages::COPY_DST, label, canvas);
+        let (layout, group) = BindGroupBuilder::new(canvas)
+            .label(label)
+            .uniform::<T>(visibility, &buffer)
+            .build();
+
+        Self { value, dirty: Cell::new(false), size, buffer, layout: layout.layout, group }
+    }
+
+    /// Entry for a `T` uniform at `binding`, for layouts that hold more than this buffer.
+    /// Checks the size of `T` like [`UniformBuffer::new`].
+    pub fn layout_entry(binding: u32, visibility: ShaderStages) -> BindGroupLayoutEntry {
+        LayoutEntry::new(binding, visibility, wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: NonZeroU64::new(Self::binding_size()),
+        })
+    }
+
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    pub fn set(&mut self, value: &T) {
+        self.value = *value;
+        self.dirty.set(true);
+    }
+
+    /// Changes the value in place, it's uploaded on the next flush even if `update` left it as it was.
+    pub fn update(&mut self, update: impl FnOnce(&mut T)) {
+        update(&mut self.value);
+        self.dirty.set(true);
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.get()
+    }
+
+    /// Uploads the value if it changed since the last flush, returns whether it did.
+    pub fn flush(&self, canvas: &Canvas) -> bool {
+        if !self.dirty.replace(false) {
+            return false;
+        }
+        canvas.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&self.value));
+        true
+    }
+
+    /// Like [`UniformBuffer::flush`] but records the upload into `encoder` through the [`Canvas::uploader`].
+    pub fn flush_staged(&self, canvas: &Canvas, encoder: &mut CommandEncoder) -> bool {
+        if !self.dirty.replace(false) {
+            return false;
+        }
+        canvas.stage_buffer(encoder, &self.buffer, 0, bytemuck::bytes_of(&self.value));
+        true
+    }
+
+    /// Size of the buffer in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn binding_size() -> u64 {
+        let size = size_of::<T>() as u64;
+        assert!(size > 0, "Uniform buffer of {} holds a zero sized type", std::any::type_name::<T>());
+        assert!(size.is_multiple_of(Self::ALIGNMENT), "{} is {} bytes, uniforms have to be padded to a multiple of {}",
+            std::any::type_name::<T>(), size, Self::ALIGNMENT);
+        size
+    }
+}
+
+/// A growable buffer of `T`s, e.g. vertices, indices or instances that change from frame to frame.
+/// Changes are made to a copy on the cpu and uploaded by [`DynamicBuffer::flush`], which only writes the range
+/// that changed. Once the elements outgrow the buffer it's replaced by one twice the size.
+pub struct DynamicBuffer<T: Pod> {
+    data: Vec<T>,
+    buffer: Buffer,
+    // In elements, what the current buffer holds
+    capacity: usize,
+    usage: BufferUsages,
+    label: Option<String>,
+    // Elements changed since the last flush
+    dirty: Option<Range<usize>>,
+}
+
+impl<T: Pod> DynamicBuffer<T> {
+    pub fn new(canvas: &Canvas, usage: BufferUsages, label: Option<&str>) -> Self {
+        Self::with_capacity(canvas, 1, usage, label)
+    }
+
+    /// `usage` always gets [`BufferUsages::COPY_DST`] added, it's needed for the uploads.
+    pub fn with_capacity(canvas: &Canvas, capacity: usize, usage: BufferUsages, label: Option<&str>) -> Self {
+        let capacity = capacity.max(1);
+        let usage = usage | BufferUsages::COPY_DST;
+
+        Self {
+            data: Vec::with_capacity(capacity),
+            buffer: Self::create_buffer(canvas, capacity, usage, label),
+            capacity,
+            usage,
+            label: label.map(str::to_string),
+            dirty: None,
+        }
+    }
+
+    pub fn from_slice(canvas: &Canvas, data: &[T], usage: BufferUsages, label: Option<&str>) -> Self {
+        let mut buffer = Self::with_capacity(canvas, data.len(), usage, label);
+        buffer.extend_from_slice(data);
+        buffer.flush(canvas);
+        buffer
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.data.push(value);
+        self.mark_dirty(self.data.len() - 1..self.data.len());
+    }
+
+    pub fn extend_from_slice(&mut self, values: &[T]) {
+        let start = self.data.len();
+        self.data.extend_from_slice(values);
+        self.mark_dirty(start..self.data.len());
+    }
+
+    /// Overwrites the elements starting at `offset`, adding the ones that go past the end.
+    /// Panics if `offset` is past the end.
+    pub fn write(&mut self, offset: usize, values: &[T]) {
+        assert!(offset <= self.data.len(), "Write at {} past the end of a buffer of {} elements", offset, self.data.len());
+
+        let overlap = values.len().min(self.data.len() - offset);
+        self.data[offset..offset + overlap].copy_from_slice(&values[..overlap]);
+        self.data.extend_from_slice(&values[overlap..]);
+        self.mark_dirty(offset..offset + values.len());
+    }
+
+    /// Removes every element, the buffer keeps its size.
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.dirty = None;
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+        self.dirty = self.dirty.take()
+            .map(|dirty| dirty.start..dirty.end.min(len))
+            .filter(|dirty| !dirty.is_empty());
+    }
+
+    pub fn data(&self) -> &[T] {
+        &self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// How many elements fit in the buffer before it has to grow.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    /// The part of the buffer holding elements, to bind as a vertex or index buffer.
+    /// Slices can't be empty, so it's the whole buffer while there are no elements.
+    pub fn slice(&self) -> BufferSlice<'_> {
+        match self.data.len() * size_of::<T>() {
+            0 => self.buffer.slice(..),
+            size => self.buffer.slice(..size as BufferAddress),
+        }
+    }
+
+    /// Uploads what changed since the last flush, growing the buffer first if the elements don't fit anymore.
+    /// Returns whether the buffer was replaced, bind groups holding it have to be created again if it was.
+    pub fn flush(&mut self, canvas: &Canvas) -> bool {
+        let grown = self.grow(canvas);
+        if let Some(dirty) = self.dirty.take() {
+            self.upload(dirty, |buffer, offset, data| canvas.write_buffer(buffer, offset, data));
+        }
+        grown
+    }
+
+    /// Like [`DynamicBuffer::flush`] but records the upload into `encoder` through the [`Canvas::uploader`].
+    pub fn flush_staged(&mut self, canvas: &Canvas, encoder: &mut CommandEncoder) -> bool {
+        let grown = self.grow(canvas);
+        if let Some(dirty) = self.dirty.take() {
+            self.upload(dirty, |buffer, offset, data| canvas.stage_buffer(encoder, buffer, offset, data));
+        }
+        grown
+    }
+
+    fn grow(&mut self, canvas: &Canvas) -> bool {
+        if self.data.len() <= self.capacity {
+            return false;
+        }
+        self.capacity = self.data.len().max(self.capacity * 2);
+        self.buffer = Self::create_buffer(canvas, self.capacity, self.usage, self.label.as_deref());
+        self.dirty = Some(0..self.data.len());
+        true
+    }
+
+    fn mark_dirty(&mut self, range: Range<usize>) {
+        self.dirty = Some(match self.dirty.take() {
+            Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+            None => range,
+        });
+    }
+
+    fn upload(&self, range: Range<usize>, write: impl FnOnce(&Buffer, BufferAddress, &[u8])) {
+        // Copies have to start and end on multiples of 4 bytes, which e.g. u16 indices don't
+        let bytes: &[u8] = bytemuck::cast_slice(&self.data);
+        let alignment = wgpu::COPY_BUFFER_ALIGNMENT as usize;
+        let start = range.start * size_of::<T>() / alignment * alignment;
+        let end = (range.end * size_of::<T>()).div_ceil(alignment) * alignment;
+
+        if end <= bytes.len() {
+            write(&self.buffer, start as BufferAddress, &bytes[start..end]);
+        } else {
+            let mut padded = bytes[start..].to_vec();
+            padded.resize(end - start, 0);
+            write(&self.buffer, start as BufferAddress, &padded);
+        }
+    }
+
+    fn create_buffer(canvas: &Canvas, capacity: usize, usage: BufferUsages, label: Option<&str>) -> Buffer {
+        let size = (capacity * size_of::<T>()) as BufferAddress;
+        canvas.device.create_buffer(&wgpu::BufferDescriptor {
+            label,
+            size: size.div_ceil(wgpu::COPY_BUFFER_ALIGNMENT) * wgpu::COPY_BUFFER_ALIGNMENT,
+            usage,
+            mapped_at_creation: false,
+        })
+    }
+}
@@ -1,65 +1,482 @@
+use anyhow::{bail, Result};
+use winit::dpi::PhysicalSize;
 use winit::window::Window;
+use crate::error::{HermitError, HermitResult};
+use crate::rendering::pipeline_cache::PipelineCache;
+use crate::rendering::render_stats::FrameStats;
+use crate::rendering::render_target::TargetFormat;
+use crate::rendering::upload::Uploader;
+use crate::util::textures::Texture;
+
+/// Settings used to pick the adapter, device and surface configuration of a canvas.
+#[derive(Clone, Debug)]
+pub struct CanvasOptions {
+    pub backends: wgpu::Backends,
+    pub power_preference: wgpu::PowerPreference,
+    pub present_mode: wgpu::PresentMode,
+    // Creation fails if one of these is missing
+    pub required_features: wgpu::Features,
+    // Enabled when the adapter supports them, silently dropped otherwise
+    pub optional_features: wgpu::Features,
+    pub limits: wgpu::Limits,
+    pub srgb: bool,
+    // Whether the canvas owns a depth buffer that is attached to every render pass
+    pub depth: bool,
+    // Msaa sample count, one of 1, 2, 4 or 8
+    pub sample_count: u32,
+    // Render the scene in floating point, it then has to be drawn through a PostStack to get tonemapped
+    pub hdr: bool,
+}
+
+impl Default for CanvasOptions {
+    fn default() -> Self {
+        Self {
+            backends: wgpu::Backends::all(),
+            power_preference: wgpu::PowerPreference::default(),
+            present_mode: wgpu::PresentMode::Fifo,
+            required_features: wgpu::Features::empty(),
+            optional_features: wgpu::Features::empty(),
+            // WebGL doesn't support all of wgpu's features, so if
+            // we're building for the web we'll have to disable some.
+            limits: if cfg!(target_arch = "wasm32") {
+                wgpu::Limits::downlevel_webgl2_defaults()
+            } else {
+                wgpu::Limits::default()
+            },
+            srgb: true,
+            depth: true,
+            sample_count: 1,
+            hdr: false,
+        }
+    }
+}
+
+impl CanvasOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_backends(mut self, backends: wgpu::Backends) -> Self {
+        self.backends = backends;
+        self
+    }
+
+    pub fn with_power_preference(mut self, power_preference: wgpu::PowerPreference) -> Self {
+        self.power_preference = power_preference;
+        self
+    }
+
+    pub fn with_present_mode(mut self, present_mode: wgpu::PresentMode) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+
+    pub fn with_required_features(mut self, features: wgpu::Features) -> Self {
+        self.required_features = features;
+        self
+    }
+
+    pub fn with_optional_features(mut self, features: wgpu::Features) -> Self {
+        self.optional_features = features;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: wgpu::Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_srgb(mut self, srgb: bool) -> Self {
+        self.srgb = srgb;
+        self
+    }
+
+    pub fn with_depth(mut self, depth: bool) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    pub fn with_sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    pub fn with_hdr(mut self, hdr: bool) -> Self {
+        self.hdr = hdr;
+        self
+    }
+}
+
+/// What was actually negotiated with the adapter when the canvas was created.
+#[derive(Clone, Debug)]
+pub struct CanvasReport {
+    pub adapter: wgpu::AdapterInfo,
+    pub features: wgpu::Features,
+    pub limits: wgpu::Limits,
+    pub present_mode: wgpu::PresentMode,
+    pub format: wgpu::TextureFormat,
+    pub sample_count: u32,
+    pub hdr: bool,
+}
 
 pub struct Canvas {
-    pub surface: wgpu::Surface,
+    pub surface: Option<wgpu::Surface>,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
-    pub size: winit::dpi::PhysicalSize<u32>,
+    pub size: PhysicalSize<u32>,
+    pub report: CanvasReport,
+    pub depth_texture: Option<Texture>,
+    pub sample_count: u32,
+    pub hdr: bool,
+    // Only set when multisampling, gets resolved into the frame at the end of a pass
+    pub msaa_view: Option<wgpu::TextureView>,
+    // Only set for headless canvases, which render into this instead of a surface
+    pub target: Option<Texture>,
+    pub pipeline_cache: PipelineCache,
+    pub stats: FrameStats,
+    pub uploader: Uploader,
 }
 
 impl Canvas {
-    pub async fn new(window: &Window) -> Self {
+    /// Color format the scene is rendered in when the canvas was created with [`CanvasOptions::hdr`].
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    pub async fn new(window: &Window, options: &CanvasOptions) -> HermitResult<Self> {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
         // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
-        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let instance = wgpu::Instance::new(options.backends);
         let surface = unsafe { instance.create_surface(window) };
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
+                power_preference: options.power_preference,
                 compatible_surface: Some(&surface),
                 force_fallback_adapter: false,
             })
             .await
-            .unwrap();
+            .ok_or(HermitError::AdapterNotFound)?;
 
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    features: wgpu::Features::empty(),
-                    // WebGL doesn't support all of wgpu's features, so if
-                    // we're building for the web we'll have to disable some.
-                    limits: if cfg!(target_arch = "wasm32") {
-                        wgpu::Limits::downlevel_webgl2_defaults()
-                    } else {
-                        wgpu::Limits::default()
-                    },
-                },
-                // Some(&std::path::Path::new("trace")), // Trace path
-                None,
-            )
-            .await
-            .unwrap();
+        let (device, queue) = Self::request_device(&adapter, options).await?;
+
+        let formats = surface.get_supported_formats(&adapter);
+        let format = *formats.iter()
+            .find(|format| format.describe().srgb == options.srgb)
+            .or_else(|| formats.first())
+            .ok_or_else(|| HermitError::SurfaceFailed("Surface is incompatible with the adapter".to_string()))?;
+
+        let present_mode = if surface.get_supported_present_modes(&adapter).contains(&options.present_mode) {
+            options.present_mode
+        } else {
+            log::warn!("Present mode {:?} is not supported, falling back to Fifo", options.present_mode);
+            wgpu::PresentMode::Fifo
+        };
 
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface.get_supported_formats(&adapter)[0],
+            format,
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode,
             alpha_mode: wgpu::CompositeAlphaMode::Auto,
         };
         surface.configure(&device, &config);
 
-        Self {
-            surface,
+        let scene_format = if options.hdr { Self::HDR_FORMAT } else { config.format };
+        let sample_count = Self::supported_sample_count(&adapter, scene_format, options.sample_count);
+        let report = Self::report(&adapter, &device, &config, sample_count, options.hdr);
+        let depth_texture = Self::create_depth_texture(&device, &config, options.depth, sample_count);
+        let msaa_view = Self::create_msaa_view(&device, &config, scene_format, sample_count);
+
+        Ok(Self {
+            surface: Some(surface),
             device,
             queue,
             config,
-            size
+            size,
+            report,
+            depth_texture,
+            sample_count,
+            hdr: options.hdr,
+            msaa_view,
+            target: None,
+            pipeline_cache: PipelineCache::new(),
+            stats: FrameStats::new(),
+            uploader: Uploader::default(),
+        })
+    }
+
+    /// Creates a canvas without a window that renders into an owned color texture.
+    /// Falls back to a software adapter if no hardware adapter is available.
+    pub async fn new_headless(size: PhysicalSize<u32>, options: &CanvasOptions) -> HermitResult<Self> {
+        let instance = wgpu::Instance::new(options.backends);
+        let mut adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: options.power_preference,
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await;
+        if adapter.is_none() {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: options.power_preference,
+                    compatible_surface: None,
+                    force_fallback_adapter: true,
+                })
+                .await;
+        }
+        let adapter = adapter.ok_or(HermitError::AdapterNotFound)?;
+
+        let (device, queue) = Self::request_device(&adapter, options).await?;
+
+        // The configuration is never handed to a surface, it just describes the target texture
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: if options.srgb { wgpu::TextureFormat::Rgba8UnormSrgb } else { wgpu::TextureFormat::Rgba8Unorm },
+            width: size.width,
+            height: size.height,
+            present_mode: options.present_mode,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        };
+        let target = Texture::create_render_target(&device, &config, "Headless Target");
+
+        let scene_format = if options.hdr { Self::HDR_FORMAT } else { config.format };
+        let sample_count = Self::supported_sample_count(&adapter, scene_format, options.sample_count);
+        let report = Self::report(&adapter, &device, &config, sample_count, options.hdr);
+        let depth_texture = Self::create_depth_texture(&device, &config, options.depth, sample_count);
+        let msaa_view = Self::create_msaa_view(&device, &config, scene_format, sample_count);
+
+        Ok(Self {
+            surface: None,
+            device,
+            queue,
+            config,
+            size,
+            report,
+            depth_texture,
+            sample_count,
+            hdr: options.hdr,
+            msaa_view,
+            target: Some(target),
+            pipeline_cache: PipelineCache::new(),
+            stats: FrameStats::new(),
+            uploader: Uploader::default(),
+        })
+    }
+
+    async fn request_device(adapter: &wgpu::Adapter, options: &CanvasOptions) -> HermitResult<(wgpu::Device, wgpu::Queue)> {
+        let supported = adapter.features();
+        if !supported.contains(options.required_features) {
+            return Err(HermitError::FeatureNotSupported(options.required_features - supported));
+        }
+
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    features: options.required_features | (options.optional_features & supported),
+                    limits: options.limits.clone(),
+                },
+                // Some(&std::path::Path::new("trace")), // Trace path
+                None,
+            )
+            .await?;
+
+        Ok((device, queue))
+    }
+
+    fn report(adapter: &wgpu::Adapter, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, sample_count: u32, hdr: bool) -> CanvasReport {
+        let report = CanvasReport {
+            adapter: adapter.get_info(),
+            features: device.features(),
+            limits: device.limits(),
+            present_mode: config.present_mode,
+            format: config.format,
+            sample_count,
+            hdr,
+        };
+        log::info!("Canvas created: {:?}", report);
+
+        report
+    }
+
+    fn supported_sample_count(adapter: &wgpu::Adapter, format: wgpu::TextureFormat, requested: u32) -> u32 {
+        if requested <= 1 { return 1; }
+
+        let supported = [format, Texture::DEPTH_FORMAT].iter().all(|format| {
+            adapter.get_texture_format_features(*format).flags.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE)
+        });
+        if supported && [2, 4, 8].contains(&requested) {
+            requested
+        } else {
+            log::warn!("Sample count {} is not supported, falling back to 1", requested);
+            1
+        }
+    }
+
+    fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, depth: bool, sample_count: u32) -> Option<Texture> {
+        if depth {
+            Some(Texture::create_depth_texture(device, config.width, config.height, sample_count, "Depth Texture"))
+        } else {
+            None
+        }
+    }
+
+    // In the scene format, which is what the sample count was checked against
+    fn create_msaa_view(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, format: wgpu::TextureFormat, sample_count: u32) -> Option<wgpu::TextureView> {
+        if sample_count > 1 {
+            Some(Texture::create_msaa_target(device, format, config.width, config.height, sample_count, "Msaa Target"))
+        } else {
+            None
+        }
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.surface.is_none()
+    }
+
+    pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        if new_size.width == 0 || new_size.height == 0 { return; }
+
+        self.size = new_size;
+        self.config.width = new_size.width;
+        self.config.height = new_size.height;
+
+        match &self.surface {
+            Some(surface) => surface.configure(&self.device, &self.config),
+            None => self.target = Some(Texture::create_render_target(&self.device, &self.config, "Headless Target")),
+        }
+
+        let depth = self.depth_texture.is_some();
+        self.depth_texture = Self::create_depth_texture(&self.device, &self.config, depth, self.sample_count);
+        self.msaa_view = Self::create_msaa_view(&self.device, &self.config, self.target_format().color, self.sample_count);
+    }
+
+    /// Format of everything drawing the scene, the surface format or [`Canvas::HDR_FORMAT`] when rendering in HDR.
+    pub fn target_format(&self) -> TargetFormat {
+        TargetFormat {
+            color: if self.hdr { Self::HDR_FORMAT } else { self.config.format },
+            sample_count: self.sample_count,
+            depth: self.depth_texture.is_some(),
+        }
+    }
+
+    /// Depth state every pipeline drawing onto this canvas has to use.
+    pub fn depth_stencil_state(&self, depth_test: bool) -> Option<wgpu::DepthStencilState> {
+        self.target_format().depth_stencil_state(depth_test)
+    }
+
+    pub fn depth_stencil_attachment(&self) -> Option<wgpu::RenderPassDepthStencilAttachment<'_>> {
+        self.depth_texture.as_ref().map(|depth_texture| wgpu::RenderPassDepthStencilAttachment {
+            view: &depth_texture.view,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(1.0),
+                store: true,
+            }),
+            stencil_ops: None,
+        })
+    }
+
+    pub fn multisample_state(&self) -> wgpu::MultisampleState {
+        self.target_format().multisample_state()
+    }
+
+    /// Color attachment that renders into the frame, going through the msaa target if there is one.
+    pub fn color_attachment<'a>(&'a self, frame: &'a CanvasFrame, load: wgpu::LoadOp<wgpu::Color>) -> wgpu::RenderPassColorAttachment<'a> {
+        let (view, resolve_target) = match &self.msaa_view {
+            Some(msaa_view) => (msaa_view, Some(&frame.view)),
+            None => (&frame.view, None),
+        };
+
+        wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target,
+            ops: wgpu::Operations {
+                load,
+                store: true,
+            },
         }
     }
-}
\ No newline at end of file
+
+    /// Begins a pass that clears the frame and attaches the depth buffer if there is one.
+    pub fn begin_render_pass<'a>(&'a self, encoder: &'a mut wgpu::CommandEncoder, frame: &'a CanvasFrame, clear_color: wgpu::Color) -> wgpu::RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[
+                // This is what @location(0) in the fragment shader targets
+                Some(self.color_attachment(frame, wgpu::LoadOp::Clear(clear_color)))
+            ],
+            depth_stencil_attachment: self.depth_stencil_attachment(),
+        })
+    }
+
+    /// Runs `build` and returns the validation errors it caused instead of letting the device panic on them.
+    /// Waits for the error scope, which only resolves right away on native backends.
+    pub fn build_checked<T>(&self, label: &str, build: impl FnOnce() -> T) -> HermitResult<T> {
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let result = build();
+        match pollster::block_on(self.device.pop_error_scope()) {
+            Some(error) => Err(HermitError::PipelineBuildFailed { label: label.to_string(), message: error.to_string() }),
+            None => Ok(result),
+        }
+    }
+
+    /// Writes `data` into `buffer` through the queue, counted in the frame's [`Canvas::stats`].
+    pub fn write_buffer(&self, buffer: &wgpu::Buffer, offset: wgpu::BufferAddress, data: &[u8]) {
+        self.queue.write_buffer(buffer, offset, data);
+        self.stats.record_upload(data.len() as u64);
+    }
+
+    /// Writes `data` into `buffer` through the [`Canvas::uploader`] as part of `encoder`, counted in the frame's [`Canvas::stats`].
+    /// The render graph finishes and recalls the uploader around submitting its encoder.
+    pub fn stage_buffer(&self, encoder: &mut wgpu::CommandEncoder, buffer: &wgpu::Buffer, offset: wgpu::BufferAddress, data: &[u8]) {
+        self.uploader.write(&self.device, encoder, buffer, offset, data);
+        self.stats.record_upload(data.len() as u64);
+    }
+
+    /// Returns the texture the next frame should be rendered into.
+    pub fn frame(&self) -> Result<CanvasFrame, wgpu::SurfaceError> {
+        match &self.surface {
+            Some(surface) => {
+                let output = surface.get_current_texture()?;
+                let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+                Ok(CanvasFrame { output: Some(output), view })
+            }
+            None => {
+                let target = self.target.as_ref().expect("Headless canvas without target");
+                let view = target.texture.create_view(&wgpu::TextureViewDescriptor::default());
+                Ok(CanvasFrame { output: None, view })
+            }
+        }
+    }
+
+    /// Copies the headless target back to the cpu as tightly packed rows in the canvas format.
+    pub fn read_pixels(&self) -> Result<Vec<u8>> {
+        let target = match &self.target {
+            Some(target) => target,
+            None => bail!("Only headless canvases can read back their pixels"),
+        };
+
+        Texture::read_pixels(&self.device, &self.queue, &target.texture, self.config.format, self.config.width, self.config.height)
+    }
+
+    pub fn to_image(&self) -> Result<image::RgbaImage> {
+        let pixels = self.read_pixels()?;
+        Texture::pixels_to_image(self.config.format, self.config.width, self.config.height, &pixels)
+    }
+}
+
+pub struct CanvasFrame {
+    output: Option<wgpu::SurfaceTexture>,
+    pub view: wgpu::TextureView,
+}
+
+impl CanvasFrame {
+    pub fn present(self) {
+        if let Some(output) = self.output {
+            output.present();
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::iter;
+use std::ops::Deref;
+use wgpu::{BindGroup, BindGroupLayout, BindGroupLayoutEntry, Buffer, BufferAddress, CommandEncoder, ComputePass};
+use crate::error::HermitResult;
+use crate::rendering::canvas::Canvas;
+use crate::rendering::shader::Shader;
+
+/// Shader holding compute entry points, loaded and compiled like a [`Shader`].
+pub struct ComputeShader {
+    pub shader: Shader,
+}
+
+impl ComputeShader {
+    pub async fn new(path: &str, canvas: &Canvas) -> HermitResult<Self> {
+        Ok(Self { shader: Shader::new(path, canvas).await? })
+    }
+
+    pub async fn from_source(source: &str, label: &str, canvas: &Canvas) -> HermitResult<Self> {
+        Ok(Self { shader: Shader::from_source(source, label, canvas).await? })
+    }
+}
+
+impl Deref for ComputeShader {
+    type Target = Shader;
+
+    fn deref(&self) -> &Self::Target {
+        &self.shader
+    }
+}
+
+pub struct ComputePipeline {
+
+}
+
+impl ComputePipeline {
+    pub fn new(canvas: &Canvas, group_layouts: &[&BindGroupLayout], label: Option<&str>, shader: &ComputeShader, entry_point: &str) -> wgpu::ComputePipeline {
+        Self::builder(canvas, shader, entry_point)
+            .label(label)
+            .group_layouts(group_layouts)
+            .build()
+    }
+
+    pub fn builder<'a>(canvas: &'a Canvas, shader: &'a ComputeShader, entry_point: &'a str) -> ComputePipelineBuilder<'a> {
+        ComputePipelineBuilder {
+            canvas,
+            label: None,
+            group_layouts: vec![],
+            shader,
+            entry_point,
+        }
+    }
+}
+
+pub struct ComputePipelineBuilder<'a> {
+    canvas: &'a Canvas,
+    label: Option<&'a str>,
+    group_layouts: Vec<&'a BindGroupLayout>,
+    shader: &'a ComputeShader,
+    entry_point: &'a str,
+}
+
+impl<'a> ComputePipelineBuilder<'a> {
+    pub fn label(mut self, label: Option<&'a str>) -> Self {
+        self.label = label;
+        self
+    }
+
+    pub fn group_layouts(mut self, group_layouts: &[&'a BindGroupLayout]) -> Self {
+        self.group_layouts = group_layouts.to_vec();
+        self
+    }
+
+    /// Checks `group_layout_entries`, the entries the group layouts were created from, against the entry point.
+    pub fn validate(self, group_layout_entries: &[&[BindGroupLayoutEntry]]) -> HermitResult<Self> {
+        self.shader.reflection().validate_bind_groups(&[self.entry_point], group_layout_entries)?;
+        Ok(self)
+    }
+
+    pub fn build(self) -> wgpu::ComputePipeline {
+        let compute_pipeline_layout =
+            self.canvas.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Compute Pipeline Layout"),
+                bind_group_layouts: &self.group_layouts,
+                push_constant_ranges: &[],
+            });
+
+        self.canvas.device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: self.label.or(Some(self.entry_point)),
+            layout: Some(&compute_pipeline_layout),
+            module: &self.shader.shader_mod,
+            entry_point: self.entry_point,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Workgroups<'a> {
+    Direct([u32; 3]),
+    // Buffer holding a wgpu::util::DispatchIndirect at the offset
+    Indirect(&'a Buffer, BufferAddress),
+}
+
+/// A single dispatch of a compute pipeline with the bind groups it needs, bound in order starting at group 0.
+/// Can be recorded into a frame's encoder, an open compute pass or submitted on its own.
+pub struct Dispatch<'a> {
+    pipeline: &'a wgpu::ComputePipeline,
+    groups: Vec<&'a BindGroup>,
+    workgroups: Workgroups<'a>,
+    label: Option<&'a str>,
+}
+
+impl<'a> Dispatch<'a> {
+    pub fn new(pipeline: &'a wgpu::ComputePipeline, x: u32, y: u32, z: u32) -> Self {
+        Self {
+            pipeline,
+            groups: vec![],
+            workgroups: Workgroups::Direct([x, y, z]),
+            label: None,
+        }
+    }
+
+    /// Enough workgroups of `workgroup_size`, which has to match the `@workgroup_size` of the entry point, to cover `size`.
+    pub fn covering(pipeline: &'a wgpu::ComputePipeline, size: [u32; 3], workgroup_size: [u32; 3]) -> Self {
+        Self::new(
+            pipeline,
+            size[0].div_ceil(workgroup_size[0]),
+            size[1].div_ceil(workgroup_size[1]),
+            size[2].div_ceil(workgroup_size[2]),
+        )
+    }
+
+    /// Reads the workgroup counts from `buffer` at `offset`, written as a [`wgpu::util::DispatchIndirect`]
+    /// by the cpu or an earlier dispatch. The buffer needs [`wgpu::BufferUsages::INDIRECT`].
+    pub fn indirect(pipeline: &'a wgpu::ComputePipeline, buffer: &'a Buffer, offset: BufferAddress) -> Self {
+        Self {
+            pipeline,
+            groups: vec![],
+            workgroups: Workgroups::Indirect(buffer, offset),
+            label: None,
+        }
+    }
+
+    pub fn label(mut self, label: &'a str) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    /// Bound at the next group index.
+    pub fn bind_group(mut self, group: &'a BindGroup) -> Self {
+        self.groups.push(group);
+        self
+    }
+
+    pub fn bind_groups(mut self, groups: &[&'a BindGroup]) -> Self {
+        self.groups.extend_from_slice(groups);
+        self
+    }
+
+    /// Records the dispatch into an open compute pass, several dispatches can share one pass.
+    pub fn record<'p>(&self, compute_pass: &mut ComputePass<'p>) where 'a: 'p {
+        compute_pass.set_pipeline(self.pipeline);
+        for (index, group) in self.groups.iter().enumerate() {
+            compute_pass.set_bind_group(index as u32, group, &[]);
+        }
+
+        match self.workgroups {
+            Workgroups::Direct([x, y, z]) => compute_pass.dispatch_workgroups(x, y, z),
+            Workgroups::Indirect(buffer, offset) => compute_pass.dispatch_workgroups_indirect(buffer, offset),
+        }
+    }
+
+    /// Records the dispatch in a compute pass of its own, e.g. into the encoder of a render graph node.
+    pub fn encode(&self, encoder: &mut CommandEncoder) {
+        Self::encode_all(encoder, iter::once(self), self.label);
+    }
+
+    /// Records the dispatches in order into one compute pass.
+    pub fn encode_all<'b>(encoder: &mut CommandEncoder, dispatches: impl IntoIterator<Item = &'b Dispatch<'a>>, label: Option<&str>) where 'a: 'b {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label });
+        for dispatch in dispatches {
+            dispatch.record(&mut compute_pass);
+        }
+    }
+
+    /// Runs the dispatch outside of a frame, in a command buffer of its own.
+    pub fn submit(&self, canvas: &Canvas) {
+        let mut encoder = canvas.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Compute Encoder"),
+        });
+        self.encode(&mut encoder);
+        canvas.queue.submit(iter::once(encoder.finish()));
+    }
+}
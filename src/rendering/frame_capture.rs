@@ -0,0 +1,78 @@
+use wgpu::TextureView;
+use crate::error::{HermitError, HermitResult};
+use crate::rendering::canvas::{Canvas, CanvasFrame};
+use crate::rendering::post_process::{load_post_shader, FullscreenPass};
+use crate::util::textures::Texture;
+
+/// Reads a rendered frame back to the cpu.
+/// Surface textures usually can't be copied from, so a captured frame is rendered into a texture
+/// of the same format instead, which then gets drawn onto the surface and read back.
+pub struct FrameCapture {
+    // Kept between captures, recreated when the canvas size changes
+    capture: Option<Texture>,
+    capture_size: (u32, u32),
+    // The view the frame was redirected from
+    surface_view: Option<TextureView>,
+    blit: FullscreenPass,
+}
+
+impl FrameCapture {
+    pub async fn new(canvas: &Canvas) -> HermitResult<Self> {
+        let shader = load_post_shader("shaders/post/copy.wgsl", canvas).await?;
+        let blit = FullscreenPass::new(canvas, &shader, "fs_main", canvas.config.format, &[], "Capture Blit");
+
+        Ok(Self {
+            capture: None,
+            capture_size: (0, 0),
+            surface_view: None,
+            blit,
+        })
+    }
+
+    /// Makes `frame` render into the capture texture, call right after [`Canvas::frame`].
+    pub fn redirect(&mut self, canvas: &Canvas, frame: &mut CanvasFrame) {
+        let size = (canvas.config.width, canvas.config.height);
+        if self.capture_size != size {
+            self.capture = None;
+        }
+        let capture = self.capture.get_or_insert_with(|| Self::create_capture(canvas, size.0, size.1));
+        self.capture_size = size;
+
+        let view = capture.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.surface_view = Some(std::mem::replace(&mut frame.view, view));
+    }
+
+    fn create_capture(canvas: &Canvas, width: u32, height: u32) -> Texture {
+        Texture::create_color_texture(
+            &canvas.device,
+            canvas.config.format,
+            width,
+            height,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            "Frame Capture",
+        )
+    }
+
+    /// Draws the captured frame onto the view it was redirected from and reads it back, call before [`CanvasFrame::present`].
+    /// Returns `None` if the frame wasn't redirected.
+    pub fn finish(&mut self, canvas: &Canvas, frame: &mut CanvasFrame) -> HermitResult<Option<image::RgbaImage>> {
+        let surface_view = match self.surface_view.take() {
+            Some(surface_view) => surface_view,
+            None => return Ok(None),
+        };
+        let capture = self.capture.as_ref().expect("Redirected frame without capture texture");
+
+        let mut encoder = canvas.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Capture Encoder"),
+        });
+        self.blit.draw(canvas, &mut encoder, capture, &surface_view, &[]);
+        canvas.queue.submit(std::iter::once(encoder.finish()));
+        frame.view = surface_view;
+
+        let (width, height) = self.capture_size;
+        Texture::read_pixels(&canvas.device, &canvas.queue, &capture.texture, canvas.config.format, width, height)
+            .and_then(|pixels| Texture::pixels_to_image(canvas.config.format, width, height, &pixels))
+            .map(Some)
+            .map_err(|error| HermitError::CaptureFailed(error.to_string()))
+    }
+}
@@ -0,0 +1,213 @@
+use wgpu::{BindGroup, BindGroupLayout, Buffer, BufferUsages, CommandEncoder, ShaderStages};
+use bytemuck::Zeroable;
+use crate::error::HermitResult;
+use crate::rendering::bind_group::{BindGroupBuilder, BindLayout, LayoutEntry};
+use crate::rendering::buffer::BufferBuilder;
+use crate::rendering::canvas::Canvas;
+use crate::rendering::compute::{ComputePipeline, ComputeShader, Dispatch};
+use crate::util::textures::Texture;
+
+/// Curve mapping HDR colors into the displayable range.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TonemapOperator {
+    Reinhard,
+    Aces,
+    Filmic,
+}
+
+impl TonemapOperator {
+    // Matches the branches in tonemap.wgsl
+    pub(crate) fn index(&self) -> u32 {
+        match self {
+            TonemapOperator::Reinhard => 0,
+            TonemapOperator::Aces => 1,
+            TonemapOperator::Filmic => 2,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Exposure {
+    Manual(f32),
+    /// Adapts to the average luminance of the scene, measured every frame.
+    Auto(AutoExposureSettings),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AutoExposureSettings {
+    /// Luminance range, as log2, the histogram covers. Anything outside gets clamped.
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+    /// Fraction of the way to the newly measured luminance covered each frame, 1.0 adapts instantly.
+    pub adaptation: f32,
+    /// Multiplier on the computed exposure, above 1.0 brightens the image.
+    pub compensation: f32,
+}
+
+impl Default for AutoExposureSettings {
+    fn default() -> Self {
+        Self {
+            min_log_luminance: -8.0,
+            max_log_luminance: 4.0,
+            adaptation: 0.05,
+            compensation: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct HistogramSettings {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    compensation: f32,
+    pixel_count: u32,
+    _padding: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ExposureValues {
+    average_luminance: f32,
+    exposure: f32,
+    _padding: [f32; 2],
+}
+
+const LUMINANCE_SHADER: &str = "shaders/post/luminance.wgsl";
+const HISTOGRAM_BINS: usize = 256;
+const HISTOGRAM_WORKGROUP_SIZE: u32 = 16;
+
+/// Measures the scene's luminance with a histogram built in compute passes and turns it into an exposure value.
+/// The exposure stays on the gpu, tonemapping reads it through [`AutoExposure::exposure_group`].
+pub struct AutoExposure {
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    // Files the luminance shader was assembled from, checked by hot reloading
+    dependencies: Vec<String>,
+    compute_layout: BindLayout,
+    histogram: Buffer,
+    exposure: Buffer,
+    settings: Buffer,
+    exposure_layout: BindGroupLayout,
+    exposure_group: BindGroup,
+}
+
+impl AutoExposure {
+    pub async fn new(canvas: &Canvas) -> HermitResult<Self> {
+        let shader = ComputeShader::new(LUMINANCE_SHADER, canvas).await?;
+
+        let compute_layout = BindLayout::new(canvas,
+            &[
+                LayoutEntry::new(0, ShaderStages::COMPUTE, wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                }),
+                LayoutEntry::storage_buffer(1, ShaderStages::COMPUTE, false),
+                LayoutEntry::storage_buffer(2, ShaderStages::COMPUTE, false),
+                LayoutEntry::new(3, ShaderStages::COMPUTE, wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                }),
+            ],
+            Some("Auto Exposure"),
+        );
+
+        let histogram_pipeline = ComputePipeline::new(canvas, &[&compute_layout.layout], None, &shader, "cs_histogram");
+        let average_pipeline = ComputePipeline::new(canvas, &[&compute_layout.layout], None, &shader, "cs_average");
+
+        let histogram = BufferBuilder::new(&[0u32; HISTOGRAM_BINS], BufferUsages::STORAGE, Some("Luminance Histogram"), canvas);
+        let exposure = BufferBuilder::new(
+            &[ExposureValues { average_luminance: 0.18, exposure: 1.0, _padding: [0.0; 2] }],
+            BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            Some("Exposure"),
+            canvas,
+        );
+        let settings = BufferBuilder::new(
+            &[HistogramSettings::zeroed()],
+            BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            Some("Auto Exposure Settings"),
+            canvas,
+        );
+
+        let (exposure_layout, exposure_group) = BindGroupBuilder::new(canvas)
+            .label(Some("Exposure"))
+            .storage_buffer(ShaderStages::FRAGMENT, true, &exposure)
+            .build();
+
+        Ok(Self {
+            histogram_pipeline,
+            average_pipeline,
+            dependencies: shader.dependencies().to_vec(),
+            compute_layout,
+            histogram,
+            exposure,
+            settings,
+            exposure_layout: exposure_layout.layout,
+            exposure_group,
+        })
+    }
+
+    /// Layout of the read only exposure buffer for fragment shaders.
+    pub fn exposure_layout(&self) -> &BindGroupLayout {
+        &self.exposure_layout
+    }
+
+    pub fn exposure_group(&self) -> &BindGroup {
+        &self.exposure_group
+    }
+
+    /// Rebuilds the compute pipelines if a file the luminance shader was assembled from is among the `changed` paths, keeping the old ones on failure.
+    pub fn reload(&mut self, canvas: &Canvas, changed: &[String]) -> HermitResult<()> {
+        if !changed.iter().any(|path| self.dependencies.contains(path)) {
+            return Ok(());
+        }
+
+        let shader = pollster::block_on(ComputeShader::new(LUMINANCE_SHADER, canvas))?;
+        let (histogram_pipeline, average_pipeline) = canvas.build_checked("Auto Exposure", || (
+            ComputePipeline::new(canvas, &[&self.compute_layout.layout], None, &shader, "cs_histogram"),
+            ComputePipeline::new(canvas, &[&self.compute_layout.layout], None, &shader, "cs_average"),
+        ))?;
+        self.histogram_pipeline = histogram_pipeline;
+        self.average_pipeline = average_pipeline;
+        self.dependencies = shader.dependencies().to_vec();
+        Ok(())
+    }
+
+    /// Overrides the exposure, until the next [`AutoExposure::measure`].
+    pub fn set_manual(&self, canvas: &Canvas, exposure: f32) {
+        let values = ExposureValues { average_luminance: 0.18 / exposure.max(0.0001), exposure, _padding: [0.0; 2] };
+        canvas.write_buffer(&self.exposure, 0, bytemuck::bytes_of(&values));
+    }
+
+    /// Records the passes updating the exposure from `scene`, they have to run before the scene gets tonemapped.
+    /// `scene` is expected to have the size of the canvas.
+    pub fn measure(&self, canvas: &Canvas, encoder: &mut CommandEncoder, scene: &Texture, settings: &AutoExposureSettings) {
+        let (width, height) = (canvas.config.width, canvas.config.height);
+        let values = HistogramSettings {
+            min_log_luminance: settings.min_log_luminance,
+            log_luminance_range: (settings.max_log_luminance - settings.min_log_luminance).max(0.0001),
+            adaptation: settings.adaptation.clamp(0.0, 1.0),
+            compensation: settings.compensation,
+            pixel_count: width * height,
+            _padding: [0; 3],
+        };
+        canvas.write_buffer(&self.settings, 0, bytemuck::bytes_of(&values));
+
+        let group = self.compute_layout.group(canvas, &[
+            wgpu::BindingResource::TextureView(&scene.view),
+            self.histogram.as_entire_binding(),
+            self.exposure.as_entire_binding(),
+            self.settings.as_entire_binding(),
+        ]);
+
+        let dispatches = [
+            Dispatch::covering(&self.histogram_pipeline, [width, height, 1], [HISTOGRAM_WORKGROUP_SIZE, HISTOGRAM_WORKGROUP_SIZE, 1])
+                .bind_group(&group),
+            Dispatch::new(&self.average_pipeline, 1, 1, 1).bind_group(&group),
+        ];
+        Dispatch::encode_all(encoder, dispatches.iter(), Some("Auto Exposure"));
+    }
+}
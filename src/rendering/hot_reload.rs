@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use crate::util::resources;
+
+/// Watches the WGSL files in the source `res/shaders` directory for changes during development.
+/// Changes are found by polling modification times. For edits to take effect without a rebuild, shaders have to be
+/// loaded from the watched directory instead of the copy `build.rs` makes in `OUT_DIR`, see
+/// [`resources::set_resource_root`] and [`ShaderWatcher::root`].
+pub struct ShaderWatcher {
+    root: PathBuf,
+    // Paths relative to the root, like the ones passed to Shader::new
+    modified: HashMap<String, Option<SystemTime>>,
+    interval: Duration,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub fn new() -> Self {
+        Self::with_root(resources::source_resource_root())
+    }
+
+    /// Watches the shaders below `root/shaders`, `root` takes the place of the `res` directory.
+    pub fn with_root(root: PathBuf) -> Self {
+        let mut watcher = Self {
+            root,
+            modified: HashMap::new(),
+            interval: Duration::from_millis(250),
+            last_poll: Instant::now(),
+        };
+        watcher.modified = watcher.scan();
+        log::info!("Watching shaders in {}", watcher.root.join("shaders").display());
+
+        watcher
+    }
+
+    /// The directory standing in for `res`.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// How often [`ShaderWatcher::poll`] actually looks at the files.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Paths of the shaders that were changed, added or removed since the last poll,
+    /// relative to the resource root like the paths passed to [`crate::rendering::shader::Shader::new`].
+    pub fn poll(&mut self) -> Vec<String> {
+        if self.last_poll.elapsed() < self.interval {
+            return vec![];
+        }
+        self.last_poll = Instant::now();
+
+        let current = self.scan();
+        let mut changed: Vec<String> = current.iter()
+            .filter(|(path, modified)| self.modified.get(*path) != Some(*modified))
+            .map(|(path, _)| path.clone())
+            .chain(self.modified.keys().filter(|path| !current.contains_key(*path)).cloned())
+            .collect();
+        changed.sort();
+
+        self.modified = current;
+        for path in changed.iter() {
+            log::info!("Shader changed: {}", path);
+        }
+        changed
+    }
+
+    fn scan(&self) -> HashMap<String, Option<SystemTime>> {
+        let mut files = HashMap::new();
+        Self::scan_directory(&self.root, &self.root.join("shaders"), &mut files);
+        files
+    }
+
+    fn scan_directory(root: &Path, directory: &Path, files: &mut HashMap<String, Option<SystemTime>>) {
+        let entries = match std::fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(error) => {
+                log::warn!("Cannot read {}: {}", directory.display(), error);
+                return;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                Self::scan_directory(root, &path, files);
+            } else if path.extension().is_some_and(|extension| extension == "wgsl") {
+                let relative = path.strip_prefix(root).unwrap_or(&path);
+                // Shader paths always use forward slashes
+                let name = relative.components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let modified = entry.metadata().and_then(|metadata| metadata.modified()).ok();
+                files.insert(name, modified);
+            }
+        }
+    }
+}
+
+impl Default for ShaderWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
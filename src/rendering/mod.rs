@@ -2,6 +2,24 @@ pub mod bind_group;
 pub mod model;
 pub mod canvas;
 pub mod shader;
+pub mod preprocessor;
+pub mod reflection;
 pub mod buffer;
 pub mod instance;
 pub mod pipeline;
+pub mod compute;
+pub mod pipeline_cache;
+pub mod render_graph;
+pub mod render_target;
+pub mod post_process;
+pub mod post_effects;
+pub mod hdr;
+pub mod frame_capture;
+pub mod screenshot;
+pub mod recorder;
+pub mod profiler;
+pub mod render_stats;
+pub mod upload;
+pub mod resource_manager;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod hot_reload;
@@ -1,4 +1,6 @@
 use std::ops::Range;
+use crate::rendering::render_stats::StatsRenderPass;
+use crate::rendering::resource_manager::Handle;
 use crate::util::textures;
 
 // model.rs
@@ -41,14 +43,16 @@ impl Vertex for ModelVertex {
     }
 }
 
+/// The parts of a model, owned by the [`crate::rendering::resource_manager::ResourceManager`] it was loaded with.
+#[derive(Clone, Debug)]
 pub struct Model {
-    pub meshes: Vec<Mesh>,
-    pub materials: Vec<Material>,
+    pub meshes: Vec<Handle<Mesh>>,
+    pub materials: Vec<Handle<Material>>,
 }
 
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: textures::Texture,
+    pub diffuse_texture: Handle<textures::Texture>,
     pub bind_group: wgpu::BindGroup,
 }
 
@@ -57,7 +61,7 @@ pub struct Mesh {
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
-    pub material: usize,
+    pub material: Handle<Material>,
 }
 
 pub trait DrawModel<'a> {
@@ -80,6 +84,29 @@ impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
         self.draw_mesh_instanced(mesh, material, 0..1, camera_bind_group);
     }
 
+    fn draw_mesh_instanced(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.draw_indexed(0..mesh.num_elements, 0, instances);
+    }
+}
+
+impl<'a, 'b> DrawModel<'b> for StatsRenderPass<'a>
+    where
+        'b: 'a,
+{
+    fn draw_mesh(&mut self, mesh: &'b Mesh, material: &'b Material, camera_bind_group: &'b wgpu::BindGroup) {
+        self.draw_mesh_instanced(mesh, material, 0..1, camera_bind_group);
+    }
+
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'b Mesh,
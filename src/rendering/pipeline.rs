@@ -1,61 +1,293 @@
-use wgpu::{BindGroupLayout, FragmentState, RenderPipeline, VertexState};
+use std::sync::Arc;
+use wgpu::{BindGroupLayout, BindGroupLayoutEntry, FragmentState, PipelineLayout, RenderPipeline, VertexState};
+use crate::error::HermitResult;
 use crate::rendering::canvas::Canvas;
-use crate::rendering::shader::FragmentEntry;
-use crate::util::textures;
+use crate::rendering::pipeline_cache::PipelineKey;
+use crate::rendering::render_target::TargetFormat;
+use crate::rendering::shader::{FragmentEntry, Shader};
 
 pub struct Pipeline {
 
 }
 
 impl Pipeline {
-    pub fn new(canvas: &Canvas, group_layouts: &[&BindGroupLayout], label: Option<&str>, vertex: VertexState, fragment: FragmentEntry) -> RenderPipeline {
-        let render_pipeline_layout =
-            canvas.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: group_layouts,
-                push_constant_ranges: &[],
-            });
-
-        let render_pipeline = canvas.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label,
-            layout: Some(&render_pipeline_layout),
+    pub fn new(canvas: &Canvas, group_layouts: &[&BindGroupLayout], label: Option<&str>, vertex: VertexState, fragment: FragmentEntry, depth_test: bool) -> RenderPipeline {
+        Self::builder(canvas, vertex, fragment)
+            .label(label)
+            .group_layouts(group_layouts)
+            .depth_test(depth_test)
+            .build()
+    }
+
+    /// Starts a pipeline with the same defaults as [`Pipeline::new`].
+    pub fn builder<'a>(canvas: &'a Canvas, vertex: VertexState<'a>, fragment: FragmentEntry<'a>) -> PipelineBuilder<'a> {
+        PipelineBuilder {
+            canvas,
+            label: None,
+            group_layouts: vec![],
+            layout: None,
             vertex,
-            fragment: Some(FragmentState {
-                entry_point: fragment.entry_point,
-                module: fragment.shader_mod,
-                targets: &[Some(wgpu::ColorTargetState { // 4.
-                    format: canvas.config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })]
-            }),
+            fragment,
+            targets: vec![Some(wgpu::ColorTargetState { // 4.
+                format: canvas.target_format().color,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
             primitive: wgpu::PrimitiveState {
                 topology: wgpu::PrimitiveTopology::TriangleList, // 1.
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw, // 2.
                 cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+                // Setting this to anything other than Fill requires Features::POLYGON_MODE_LINE or POINT
                 polygon_mode: wgpu::PolygonMode::Fill,
                 // Requires Features::DEPTH_CLIP_CONTROL
                 unclipped_depth: false,
                 // Requires Features::CONSERVATIVE_RASTERIZATION
                 conservative: false,
             },
-            depth_stencil: /*Some(wgpu::DepthStencilState {
-                format: textures::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less, // 1.
-                stencil: wgpu::StencilState::default(), // 2.
-                bias: wgpu::DepthBiasState::default(),
-            })*/ None,
-            multisample: wgpu::MultisampleState {
-                count: 1, // 2.
-                mask: !0, // 3.
-                alpha_to_coverage_enabled: false, // 4.
+            target: canvas.target_format(),
+            depth_test: false,
+            depth_stencil: canvas.depth_stencil_state(false),
+            multisample: canvas.multisample_state(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    Replace,
+    Alpha,
+    Additive,
+    Premultiplied,
+}
+
+impl BlendMode {
+    pub fn state(&self) -> wgpu::BlendState {
+        match self {
+            BlendMode::Replace => wgpu::BlendState::REPLACE,
+            BlendMode::Alpha => wgpu::BlendState::ALPHA_BLENDING,
+            BlendMode::Additive => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
             },
-            multiview: None, // 5.
+            BlendMode::Premultiplied => wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+        }
+    }
+}
+
+pub struct PipelineBuilder<'a> {
+    canvas: &'a Canvas,
+    label: Option<&'a str>,
+    group_layouts: Vec<&'a BindGroupLayout>,
+    layout: Option<&'a PipelineLayout>,
+    vertex: VertexState<'a>,
+    fragment: FragmentEntry<'a>,
+    targets: Vec<Option<wgpu::ColorTargetState>>,
+    primitive: wgpu::PrimitiveState,
+    target: TargetFormat,
+    depth_test: bool,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    multisample: wgpu::MultisampleState,
+}
+
+impl<'a> PipelineBuilder<'a> {
+    pub fn label(mut self, label: Option<&'a str>) -> Self {
+        self.label = label;
+        self
+    }
+
+    pub fn group_layouts(mut self, group_layouts: &[&'a BindGroupLayout]) -> Self {
+        self.group_layouts = group_layouts.to_vec();
+        self
+    }
+
+    /// Uses an existing layout instead of creating one from the group layouts, which lets a pipeline
+    /// be rebuilt without keeping the group layouts around.
+    pub fn layout(mut self, layout: &'a PipelineLayout) -> Self {
+        self.layout = Some(layout);
+        self
+    }
+
+    /// Applies the blend mode to every color target.
+    pub fn blend(mut self, blend: BlendMode) -> Self {
+        for target in self.targets.iter_mut().flatten() {
+            target.blend = Some(blend.state());
+        }
+        self
+    }
+
+    /// Replaces the default canvas target, one entry per `@location` of the fragment shader.
+    pub fn targets(mut self, targets: &[Option<wgpu::ColorTargetState>]) -> Self {
+        self.targets = targets.to_vec();
+        self
+    }
+
+    pub fn add_target(mut self, format: wgpu::TextureFormat, blend: BlendMode) -> Self {
+        self.targets.push(Some(wgpu::ColorTargetState {
+            format,
+            blend: Some(blend.state()),
+            write_mask: wgpu::ColorWrites::ALL,
+        }));
+        self
+    }
+
+    /// Strips drawn with an index buffer also need [`PipelineBuilder::strip_index_format`].
+    pub fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.primitive.topology = topology;
+        if !topology.is_strip() {
+            self.primitive.strip_index_format = None;
+        }
+        self
+    }
+
+    /// Format of the index buffer strips are drawn with, `None` for strips drawn without one.
+    pub fn strip_index_format(mut self, format: Option<wgpu::IndexFormat>) -> Self {
+        self.primitive.strip_index_format = format;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.primitive.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn front_face(mut self, front_face: wgpu::FrontFace) -> Self {
+        self.primitive.front_face = front_face;
+        self
+    }
+
+    /// Falls back to [`wgpu::PolygonMode::Fill`] if the device lacks the feature for the requested mode.
+    pub fn polygon_mode(mut self, polygon_mode: wgpu::PolygonMode) -> Self {
+        let required = match polygon_mode {
+            wgpu::PolygonMode::Fill => wgpu::Features::empty(),
+            wgpu::PolygonMode::Line => wgpu::Features::POLYGON_MODE_LINE,
+            wgpu::PolygonMode::Point => wgpu::Features::POLYGON_MODE_POINT,
+        };
+
+        if self.canvas.device.features().contains(required) {
+            self.primitive.polygon_mode = polygon_mode;
+        } else {
+            log::warn!("Polygon mode {:?} requires {:?}, falling back to Fill", polygon_mode, required);
+            self.primitive.polygon_mode = wgpu::PolygonMode::Fill;
+        }
+        self
+    }
+
+    /// Renders into `target` instead of the canvas, e.g. a [`crate::rendering::render_target::RenderTarget`].
+    /// Sets the first color target as well as the depth and multisample state.
+    pub fn target(mut self, target: TargetFormat) -> Self {
+        let blend = self.targets.first().cloned().flatten().and_then(|state| state.blend);
+        if self.targets.is_empty() {
+            self.targets.push(None);
+        }
+        self.targets[0] = Some(wgpu::ColorTargetState {
+            format: target.color,
+            blend: blend.or(Some(wgpu::BlendState::REPLACE)),
+            write_mask: wgpu::ColorWrites::ALL,
         });
+        self.target = target;
+        self.depth_stencil = target.depth_stencil_state(self.depth_test);
+        self.multisample = target.multisample_state();
+        self
+    }
+
+    /// Uses the depth state of the target, testing and writing depth if `depth_test` is set.
+    pub fn depth_test(mut self, depth_test: bool) -> Self {
+        self.depth_test = depth_test;
+        self.depth_stencil = self.target.depth_stencil_state(depth_test);
+        self
+    }
+
+    pub fn depth_stencil(mut self, depth_stencil: Option<wgpu::DepthStencilState>) -> Self {
+        self.depth_stencil = depth_stencil;
+        self
+    }
+
+    pub fn multisample(mut self, multisample: wgpu::MultisampleState) -> Self {
+        self.multisample = multisample;
+        self
+    }
 
-        render_pipeline
+    /// Checks the vertex buffers and `group_layout_entries`, the entries the group layouts were created from,
+    /// against what the entry points in `shader` declare. Catches mismatches with an error naming the binding
+    /// or location instead of a panic inside wgpu.
+    pub fn validate(self, shader: &Shader, group_layout_entries: &[&[BindGroupLayoutEntry]]) -> HermitResult<Self> {
+        let reflection = shader.reflection();
+        reflection.validate_vertex_buffers(self.vertex.entry_point, self.vertex.buffers)?;
+        reflection.validate_bind_groups(&[self.vertex.entry_point, self.fragment.entry_point], group_layout_entries)?;
+        Ok(self)
     }
-}
\ No newline at end of file
+
+    /// Key identifying this pipeline in the [`crate::rendering::pipeline_cache::PipelineCache`].
+    /// `shader` has to be the shader both entries point into and `group_layout_entries`
+    /// the entries the group layouts were created from.
+    pub fn key(&self, shader: &Shader, group_layout_entries: &[&[BindGroupLayoutEntry]]) -> PipelineKey {
+        debug_assert_eq!(group_layout_entries.len(), self.group_layouts.len());
+
+        PipelineKey::new(
+            shader.id(),
+            self.vertex.entry_point,
+            self.fragment.entry_point,
+            self.vertex.buffers,
+            group_layout_entries,
+            &self.targets,
+            self.primitive,
+            &self.depth_stencil,
+            self.multisample,
+        )
+    }
+
+    /// Like [`PipelineBuilder::build`] but shares the pipeline with every identical one built through the canvas cache.
+    pub fn build_cached(self, shader: &Shader, group_layout_entries: &[&[BindGroupLayoutEntry]]) -> Arc<RenderPipeline> {
+        let key = self.key(shader, group_layout_entries);
+        let canvas = self.canvas;
+        canvas.pipeline_cache.get_or_insert_with(key, || self.build())
+    }
+
+    /// Like [`PipelineBuilder::build_cached`] but builds under a validation error scope, a pipeline the device
+    /// rejects is returned as an error and never cached.
+    pub fn build_cached_checked(self, shader: &Shader, group_layout_entries: &[&[BindGroupLayoutEntry]]) -> HermitResult<Arc<RenderPipeline>> {
+        let key = self.key(shader, group_layout_entries);
+        let canvas = self.canvas;
+        let label = self.label.unwrap_or("Render Pipeline");
+        canvas.pipeline_cache.try_get_or_insert_with(key, || canvas.build_checked(label, || self.build()))
+    }
+
+    pub fn build(self) -> RenderPipeline {
+        let created_layout;
+        let render_pipeline_layout = match self.layout {
+            Some(layout) => layout,
+            None => {
+                created_layout = self.canvas.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Render Pipeline Layout"),
+                    bind_group_layouts: &self.group_layouts,
+                    push_constant_ranges: &[],
+                });
+                &created_layout
+            }
+        };
+
+        self.canvas.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: self.label,
+            layout: Some(render_pipeline_layout),
+            vertex: self.vertex,
+            fragment: Some(FragmentState {
+                entry_point: self.fragment.entry_point,
+                module: self.fragment.shader_mod,
+                targets: &self.targets,
+            }),
+            primitive: self.primitive,
+            depth_stencil: self.depth_stencil,
+            multisample: self.multisample,
+            multiview: None, // 5.
+        })
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use wgpu::{BindGroupLayoutEntry, RenderPipeline, VertexBufferLayout};
+
+/// Everything that decides how a render pipeline is built.
+/// Two builders with the same key produce interchangeable pipelines.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    shader: u64,
+    vertex_entry: String,
+    fragment_entry: String,
+    vertex_layouts: Vec<(wgpu::BufferAddress, wgpu::VertexStepMode, Vec<wgpu::VertexAttribute>)>,
+    group_layouts: Vec<Vec<BindGroupLayoutEntry>>,
+    targets: Vec<Option<wgpu::ColorTargetState>>,
+    primitive: wgpu::PrimitiveState,
+    // DepthStencilState holds floats so it can't be hashed directly
+    depth_stencil: Option<(wgpu::TextureFormat, bool, wgpu::CompareFunction, wgpu::StencilState, i32, u32, u32)>,
+    multisample: wgpu::MultisampleState,
+}
+
+impl PipelineKey {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        shader: u64,
+        vertex_entry: &str,
+        fragment_entry: &str,
+        vertex_layouts: &[VertexBufferLayout],
+        group_layouts: &[&[BindGroupLayoutEntry]],
+        targets: &[Option<wgpu::ColorTargetState>],
+        primitive: wgpu::PrimitiveState,
+        depth_stencil: &Option<wgpu::DepthStencilState>,
+        multisample: wgpu::MultisampleState,
+    ) -> Self {
+        Self {
+            shader,
+            vertex_entry: vertex_entry.to_string(),
+            fragment_entry: fragment_entry.to_string(),
+            vertex_layouts: vertex_layouts.iter()
+                .map(|layout| (layout.array_stride, layout.step_mode, layout.attributes.to_vec()))
+                .collect(),
+            group_layouts: group_layouts.iter().map(|entries| entries.to_vec()).collect(),
+            targets: targets.to_vec(),
+            primitive,
+            depth_stencil: depth_stencil.as_ref().map(|state| (
+                state.format,
+                state.depth_write_enabled,
+                state.depth_compare,
+                state.stencil.clone(),
+                state.bias.constant,
+                state.bias.slope_scale.to_bits(),
+                state.bias.clamp.to_bits(),
+            )),
+            multisample,
+        }
+    }
+}
+
+/// Shares render pipelines between everything that would otherwise build identical ones.
+#[derive(Default)]
+pub struct PipelineCache {
+    pipelines: Mutex<HashMap<PipelineKey, Arc<RenderPipeline>>>,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the cached pipeline for `key` or builds and caches it with `build`.
+    pub fn get_or_insert_with(&self, key: PipelineKey, build: impl FnOnce() -> RenderPipeline) -> Arc<RenderPipeline> {
+        let mut pipelines = self.pipelines.lock().unwrap();
+        pipelines.entry(key).or_insert_with(|| Arc::new(build())).clone()
+    }
+
+    /// Like [`PipelineCache::get_or_insert_with`] for builds that can fail, nothing is cached if `build` does.
+    pub fn try_get_or_insert_with<E>(&self, key: PipelineKey, build: impl FnOnce() -> Result<RenderPipeline, E>) -> Result<Arc<RenderPipeline>, E> {
+        let mut pipelines = self.pipelines.lock().unwrap();
+        if let Some(pipeline) = pipelines.get(&key) {
+            return Ok(pipeline.clone());
+        }
+
+        let pipeline = Arc::new(build()?);
+        pipelines.insert(key, pipeline.clone());
+        Ok(pipeline)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pipelines.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets the pipelines built from the shader with `shader_id`, e.g. after it was reloaded.
+    pub fn remove_shader(&self, shader_id: u64) {
+        self.pipelines.lock().unwrap().retain(|key, _| key.shader != shader_id);
+    }
+
+    /// Drops the cache's references, pipelines still in use stay alive until their users drop them.
+    pub fn clear(&self) {
+        self.pipelines.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(shader: u64) -> PipelineKey {
+        PipelineKey::new(
+            shader,
+            "vs_main",
+            "fs_main",
+            &[],
+            &[],
+            &[],
+            wgpu::PrimitiveState::default(),
+            &None,
+            wgpu::MultisampleState::default(),
+        )
+    }
+
+    #[test]
+    fn failed_builds_are_not_cached() {
+        let cache = PipelineCache::new();
+
+        let result = cache.try_get_or_insert_with(key(1), || Err("invalid shader"));
+        assert_eq!(result.err(), Some("invalid shader"));
+        assert!(cache.is_empty());
+
+        // The next request builds again instead of getting a broken pipeline
+        let mut built = false;
+        let _ = cache.try_get_or_insert_with(key(1), || {
+            built = true;
+            Err("still invalid")
+        });
+        assert!(built);
+        assert!(cache.is_empty());
+    }
+}
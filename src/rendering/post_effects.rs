@@ -0,0 +1,545 @@
+use wgpu::{BindGroup, BindGroupLayout, CommandEncoder, ShaderStages, TextureView};
+use wgpu::BindingResource::{Sampler, TextureView as TextureViewResource};
+use crate::error::HermitResult;
+use crate::rendering::bind_group::{BindGroupBuilder, BindLayout, LayoutEntry};
+use crate::rendering::canvas::Canvas;
+use crate::rendering::hdr::{AutoExposure, AutoExposureSettings, Exposure, TonemapOperator};
+use crate::rendering::post_process::{load_post_shader, reload_post_shader, EffectParams, FullscreenPass, PostEffect};
+use crate::util::textures::Texture;
+
+const TONEMAP_SHADER: &str = "shaders/post/tonemap.wgsl";
+const GAMMA_SHADER: &str = "shaders/post/gamma.wgsl";
+const FXAA_SHADER: &str = "shaders/post/fxaa.wgsl";
+const BLOOM_SHADER: &str = "shaders/post/bloom.wgsl";
+const VIGNETTE_SHADER: &str = "shaders/post/vignette.wgsl";
+const COLOR_GRADING_SHADER: &str = "shaders/post/color_grading.wgsl";
+
+// Settings are uploaded as uniforms, so every struct is padded to 16 bytes
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapSettings {
+    curve: u32,
+    _padding: [u32; 3],
+}
+
+/// Maps colors above 1.0 back into the displayable range, added first to the stack of an HDR canvas.
+pub struct Tonemap {
+    pub operator: TonemapOperator,
+    pub exposure: Exposure,
+    params: EffectParams,
+    auto_exposure: AutoExposure,
+    pass: FullscreenPass,
+}
+
+impl Tonemap {
+    pub async fn new(canvas: &Canvas) -> HermitResult<Self> {
+        let operator = TonemapOperator::Reinhard;
+        let params = EffectParams::new(canvas, bytemuck::bytes_of(&Self::settings(operator)), "Tonemap Settings");
+        let auto_exposure = AutoExposure::new(canvas).await?;
+        let shader = load_post_shader(TONEMAP_SHADER, canvas).await?;
+        let pass = FullscreenPass::new(canvas, &shader, "fs_main", canvas.config.format, &[&params.layout, auto_exposure.exposure_layout()], "Tonemap");
+
+        Ok(Self { operator, exposure: Exposure::Manual(1.0), params, auto_exposure, pass })
+    }
+
+    fn settings(operator: TonemapOperator) -> TonemapSettings {
+        TonemapSettings { curve: operator.index(), _padding: [0; 3] }
+    }
+
+    pub fn with_operator(mut self, operator: TonemapOperator) -> Self {
+        self.operator = operator;
+        self
+    }
+
+    pub fn with_exposure(mut self, exposure: f32) -> Self {
+        self.exposure = Exposure::Manual(exposure);
+        self
+    }
+
+    pub fn with_auto_exposure(mut self, settings: AutoExposureSettings) -> Self {
+        self.exposure = Exposure::Auto(settings);
+        self
+    }
+}
+
+impl PostEffect for Tonemap {
+    fn name(&self) -> &str {
+        "tonemap"
+    }
+
+    fn apply(&self, canvas: &Canvas, encoder: &mut CommandEncoder, input: &Texture, output: &TextureView) {
+        self.params.write(canvas, encoder, bytemuck::bytes_of(&Self::settings(self.operator)));
+        match &self.exposure {
+            Exposure::Manual(exposure) => self.auto_exposure.set_manual(canvas, *exposure),
+            Exposure::Auto(settings) => self.auto_exposure.measure(canvas, encoder, input, settings),
+        }
+        self.pass.draw(canvas, encoder, input, output, &[&self.params.group, self.auto_exposure.exposure_group()]);
+    }
+
+    fn reload(&mut self, canvas: &Canvas, changed: &[String]) -> HermitResult<()> {
+        self.auto_exposure.reload(canvas, changed)?;
+        if self.pass.depends_on(changed) {
+            self.pass.rebuild(canvas, &reload_post_shader(TONEMAP_SHADER, canvas)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GammaSettings {
+    pub gamma: f32,
+    _padding: [f32; 3],
+}
+
+/// Gamma correction, only needed when the canvas isn't using an sRGB format.
+pub struct Gamma {
+    pub settings: GammaSettings,
+    params: EffectParams,
+    pass: FullscreenPass,
+}
+
+impl Gamma {
+    pub async fn new(canvas: &Canvas) -> HermitResult<Self> {
+        let settings = GammaSettings { gamma: 2.2, _padding: [0.0; 3] };
+        let params = EffectParams::new(canvas, bytemuck::bytes_of(&settings), "Gamma Settings");
+        let shader = load_post_shader(GAMMA_SHADER, canvas).await?;
+        let pass = FullscreenPass::new(canvas, &shader, "fs_main", canvas.config.format, &[&params.layout], "Gamma");
+
+        Ok(Self { settings, params, pass })
+    }
+
+    pub fn with_gamma(mut self, gamma: f32) -> Self {
+        self.settings.gamma = gamma;
+        self
+    }
+}
+
+impl PostEffect for Gamma {
+    fn name(&self) -> &str {
+        "gamma"
+    }
+
+    fn apply(&self, canvas: &Canvas, encoder: &mut CommandEncoder, input: &Texture, output: &TextureView) {
+        self.params.write(canvas, encoder, bytemuck::bytes_of(&self.settings));
+        self.pass.draw(canvas, encoder, input, output, &[&self.params.group]);
+    }
+
+    fn reload(&mut self, canvas: &Canvas, changed: &[String]) -> HermitResult<()> {
+        if self.pass.depends_on(changed) {
+            self.pass.rebuild(canvas, &reload_post_shader(GAMMA_SHADER, canvas)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FxaaSettings {
+    /// Maximum distance in pixels an edge is blurred along.
+    pub span_max: f32,
+    pub reduce_mul: f32,
+    pub reduce_min: f32,
+    _padding: f32,
+}
+
+/// Fast approximate anti-aliasing, a cheap alternative to multisampling the canvas.
+pub struct Fxaa {
+    pub settings: FxaaSettings,
+    params: EffectParams,
+    pass: FullscreenPass,
+}
+
+impl Fxaa {
+    pub async fn new(canvas: &Canvas) -> HermitResult<Self> {
+        let settings = FxaaSettings { span_max: 8.0, reduce_mul: 1.0 / 8.0, reduce_min: 1.0 / 128.0, _padding: 0.0 };
+        let params = EffectParams::new(canvas, bytemuck::bytes_of(&settings), "FXAA Settings");
+        let shader = load_post_shader(FXAA_SHADER, canvas).await?;
+        let pass = FullscreenPass::new(canvas, &shader, "fs_main", canvas.config.format, &[&params.layout], "FXAA");
+
+        Ok(Self { settings, params, pass })
+    }
+}
+
+impl PostEffect for Fxaa {
+    fn name(&self) -> &str {
+        "fxaa"
+    }
+
+    fn apply(&self, canvas: &Canvas, encoder: &mut CommandEncoder, input: &Texture, output: &TextureView) {
+        self.params.write(canvas, encoder, bytemuck::bytes_of(&self.settings));
+        self.pass.draw(canvas, encoder, input, output, &[&self.params.group]);
+    }
+
+    fn reload(&mut self, canvas: &Canvas, changed: &[String]) -> HermitResult<()> {
+        if self.pass.depends_on(changed) {
+            self.pass.rebuild(canvas, &reload_post_shader(FXAA_SHADER, canvas)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BloomSettings {
+    /// Brightness above which pixels start to glow.
+    pub threshold: f32,
+    pub intensity: f32,
+    _padding: [f32; 2],
+}
+
+/// Blurs the bright parts of the image at half resolution and adds them back on top.
+pub struct Bloom {
+    pub settings: BloomSettings,
+    params: EffectParams,
+    threshold: FullscreenPass,
+    blur_horizontal: FullscreenPass,
+    blur_vertical: FullscreenPass,
+    composite: FullscreenPass,
+    // Half resolution targets the blur ping-pongs between
+    targets: [Texture; 2],
+    targets_layout: BindLayout,
+}
+
+impl Bloom {
+    pub async fn new(canvas: &Canvas) -> HermitResult<Self> {
+        let settings = BloomSettings { threshold: 0.8, intensity: 1.0, _padding: [0.0; 2] };
+        let params = EffectParams::new(canvas, bytemuck::bytes_of(&settings), "Bloom Settings");
+        let targets_layout = BindLayout::texture_2d(canvas, Some("Bloom Texture"));
+
+        let shader = load_post_shader(BLOOM_SHADER, canvas).await?;
+        let format = canvas.config.format;
+        let threshold = FullscreenPass::new(canvas, &shader, "fs_threshold", format, &[&params.layout], "Bloom Threshold");
+        let blur_horizontal = FullscreenPass::new(canvas, &shader, "fs_blur_horizontal", format, &[], "Bloom Blur Horizontal");
+        let blur_vertical = FullscreenPass::new(canvas, &shader, "fs_blur_vertical", format, &[], "Bloom Blur Vertical");
+        let composite = FullscreenPass::new(canvas, &shader, "fs_composite", format, &[&params.layout, &targets_layout.layout], "Bloom Composite");
+
+        Ok(Self {
+            settings,
+            params,
+            threshold,
+            blur_horizontal,
+            blur_vertical,
+            composite,
+            targets: Self::create_targets(canvas, canvas.config.width, canvas.config.height),
+            targets_layout,
+        })
+    }
+
+    fn create_targets(canvas: &Canvas, width: u32, height: u32) -> [Texture; 2] {
+        let create = |label| Texture::create_color_texture(
+            &canvas.device,
+            canvas.config.format,
+            (width / 2).max(1),
+            (height / 2).max(1),
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            label,
+        );
+
+        [create("Bloom Target A"), create("Bloom Target B")]
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.settings.threshold = threshold;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.settings.intensity = intensity;
+        self
+    }
+}
+
+impl PostEffect for Bloom {
+    fn name(&self) -> &str {
+        "bloom"
+    }
+
+    fn resize(&mut self, canvas: &Canvas, width: u32, height: u32) {
+        self.targets = Self::create_targets(canvas, width, height);
+    }
+
+    fn apply(&self, canvas: &Canvas, encoder: &mut CommandEncoder, input: &Texture, output: &TextureView) {
+        self.params.write(canvas, encoder, bytemuck::bytes_of(&self.settings));
+
+        let [first, second] = &self.targets;
+        self.threshold.draw(canvas, encoder, input, &first.view, &[&self.params.group]);
+        self.blur_horizontal.draw(canvas, encoder, first, &second.view, &[]);
+        self.blur_vertical.draw(canvas, encoder, second, &first.view, &[]);
+
+        let bloom_group = self.targets_layout.group(canvas, &[TextureViewResource(&first.view), Sampler(&first.sampler)]);
+        self.composite.draw(canvas, encoder, input, output, &[&self.params.group, &bloom_group]);
+    }
+
+    fn reload(&mut self, canvas: &Canvas, changed: &[String]) -> HermitResult<()> {
+        if self.threshold.depends_on(changed) {
+            let shader = reload_post_shader(BLOOM_SHADER, canvas)?;
+            // All or nothing, passes built from different versions of the shader might not fit together
+            let pipelines = [
+                self.threshold.build_from(canvas, &shader)?,
+                self.blur_horizontal.build_from(canvas, &shader)?,
+                self.blur_vertical.build_from(canvas, &shader)?,
+                self.composite.build_from(canvas, &shader)?,
+            ];
+            let passes = [&mut self.threshold, &mut self.blur_horizontal, &mut self.blur_vertical, &mut self.composite];
+            for (pass, pipeline) in passes.into_iter().zip(pipelines) {
+                pass.replace_pipeline(pipeline, &shader);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct VignetteSettings {
+    pub intensity: f32,
+    /// Distance from the center, in uv units, at which darkening is complete.
+    pub radius: f32,
+    /// Width of the transition towards `radius`.
+    pub smoothness: f32,
+    _padding: f32,
+}
+
+/// Darkens the image towards its corners.
+pub struct Vignette {
+    pub settings: VignetteSettings,
+    params: EffectParams,
+    pass: FullscreenPass,
+}
+
+impl Vignette {
+    pub async fn new(canvas: &Canvas) -> HermitResult<Self> {
+        let settings = VignetteSettings { intensity: 1.0, radius: 0.8, smoothness: 0.5, _padding: 0.0 };
+        let params = EffectParams::new(canvas, bytemuck::bytes_of(&settings), "Vignette Settings");
+        let shader = load_post_shader(VIGNETTE_SHADER, canvas).await?;
+        let pass = FullscreenPass::new(canvas, &shader, "fs_main", canvas.config.format, &[&params.layout], "Vignette");
+
+        Ok(Self { settings, params, pass })
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.settings.intensity = intensity;
+        self
+    }
+}
+
+impl PostEffect for Vignette {
+    fn name(&self) -> &str {
+        "vignette"
+    }
+
+    fn apply(&self, canvas: &Canvas, encoder: &mut CommandEncoder, input: &Texture, output: &TextureView) {
+        self.params.write(canvas, encoder, bytemuck::bytes_of(&self.settings));
+        self.pass.draw(canvas, encoder, input, output, &[&self.params.group]);
+    }
+
+    fn reload(&mut self, canvas: &Canvas, changed: &[String]) -> HermitResult<()> {
+        if self.pass.depends_on(changed) {
+            self.pass.rebuild(canvas, &reload_post_shader(VIGNETTE_SHADER, canvas)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ColorGradingSettings {
+    /// Blend between the original (0.0) and the graded (1.0) colors.
+    pub intensity: f32,
+    lut_size: f32,
+    _padding: [f32; 2],
+}
+
+/// Remaps colors through a 3D lookup table.
+pub struct ColorGrading {
+    pub settings: ColorGradingSettings,
+    params: EffectParams,
+    pass: FullscreenPass,
+    lut_group: BindGroup,
+}
+
+impl ColorGrading {
+    /// `lut` is a strip of `size` square slices laid out horizontally, so `size * size` pixels wide and `size` high.
+    /// Red grows along x inside a slice, green along y and blue from slice to slice.
+    pub async fn new(canvas: &Canvas, lut: &image::RgbaImage) -> HermitResult<Self> {
+        let size = lut.height();
+        if size == 0 || lut.width() != size * size {
+            return Err(crate::error::HermitError::AssetLoadFailed {
+                name: "color grading LUT".to_string(),
+                message: format!("expected a {}x{} strip, got {}x{}", size * size, size, lut.width(), lut.height()),
+            });
+        }
+
+        let settings = ColorGradingSettings { intensity: 1.0, lut_size: size as f32, _padding: [0.0; 2] };
+        let params = EffectParams::new(canvas, bytemuck::bytes_of(&settings), "Color Grading Settings");
+
+        let (lut_layout, lut_group) = Self::create_lut(canvas, lut, size);
+        let shader = load_post_shader(COLOR_GRADING_SHADER, canvas).await?;
+        let pass = FullscreenPass::new(canvas, &shader, "fs_main", canvas.config.format, &[&params.layout, &lut_layout], "Color Grading");
+
+        Ok(Self { settings, params, pass, lut_group })
+    }
+
+    /// LUT leaving every color unchanged, a starting point for authoring new ones.
+    pub fn identity_lut(size: u32) -> image::RgbaImage {
+        let scale = 255.0 / (size - 1).max(1) as f32;
+        image::RgbaImage::from_fn(size * size, size, |x, y| {
+            let (red, green, blue) = (x % size, y, x / size);
+            image::Rgba([
+                (red as f32 * scale).round() as u8,
+                (green as f32 * scale).round() as u8,
+                (blue as f32 * scale).round() as u8,
+                255,
+            ])
+        })
+    }
+
+    fn create_lut(canvas: &Canvas, lut: &image::RgbaImage, size: u32) -> (BindGroupLayout, BindGroup) {
+        let extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: size,
+        };
+        let texture = canvas.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Color Grading LUT"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+
+        // Reorder the strip into slices, one after another
+        let mut data = Vec::with_capacity((size * size * size * 4) as usize);
+        for blue in 0..size {
+            for green in 0..size {
+                for red in 0..size {
+                    data.extend_from_slice(&lut.get_pixel(blue * size + red, green).0);
+                }
+            }
+        }
+
+        canvas.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(4 * size),
+                rows_per_image: std::num::NonZeroU32::new(size),
+            },
+            extent,
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = canvas.device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let (layout, group) = BindGroupBuilder::new(canvas)
+            .label(Some("Color Grading LUT"))
+            .entry(LayoutEntry::new(0, ShaderStages::FRAGMENT, wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D3,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            }), TextureViewResource(&view))
+            .sampler(ShaderStages::FRAGMENT, &sampler)
+            .build();
+
+        (layout.layout, group)
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.settings.intensity = intensity;
+        self
+    }
+}
+
+impl PostEffect for ColorGrading {
+    fn name(&self) -> &str {
+        "color_grading"
+    }
+
+    fn apply(&self, canvas: &Canvas, encoder: &mut CommandEncoder, input: &Texture, output: &TextureView) {
+        self.params.write(canvas, encoder, bytemuck::bytes_of(&self.settings));
+        self.pass.draw(canvas, encoder, input, output, &[&self.params.group, &self.lut_group]);
+    }
+
+    fn reload(&mut self, canvas: &Canvas, changed: &[String]) -> HermitResult<()> {
+        if self.pass.depends_on(changed) {
+            self.pass.rebuild(canvas, &reload_post_shader(COLOR_GRADING_SHADER, canvas)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Effect written in WGSL by the user. The file only contains a `fs_main` fragment entry taking a `FullscreenOutput`,
+/// the input is available as `t_input`/`s_input` and optional settings as a uniform at group 1 binding 0.
+pub struct CustomEffect {
+    name: String,
+    path: String,
+    settings: Option<(EffectParams, Vec<u8>)>,
+    pass: FullscreenPass,
+}
+
+impl CustomEffect {
+    pub async fn new(canvas: &Canvas, name: &str, path: &str) -> HermitResult<Self> {
+        let shader = load_post_shader(path, canvas).await?;
+        let pass = FullscreenPass::new(canvas, &shader, "fs_main", canvas.config.format, &[], name);
+
+        Ok(Self { name: name.to_string(), path: path.to_string(), settings: None, pass })
+    }
+
+    /// Like [`CustomEffect::new`] with `settings` bound as uniform, it has to be padded to 16 bytes.
+    pub async fn with_settings<T: bytemuck::Pod>(canvas: &Canvas, name: &str, path: &str, settings: &T) -> HermitResult<Self> {
+        let bytes = bytemuck::bytes_of(settings).to_vec();
+        let params = EffectParams::new(canvas, &bytes, name);
+        let shader = load_post_shader(path, canvas).await?;
+        let pass = FullscreenPass::new(canvas, &shader, "fs_main", canvas.config.format, &[&params.layout], name);
+
+        Ok(Self { name: name.to_string(), path: path.to_string(), settings: Some((params, bytes)), pass })
+    }
+
+    /// Does nothing if the effect was created without settings.
+    pub fn set_settings<T: bytemuck::Pod>(&mut self, settings: &T) {
+        if let Some((_, bytes)) = &mut self.settings {
+            *bytes = bytemuck::bytes_of(settings).to_vec();
+        }
+    }
+}
+
+impl PostEffect for CustomEffect {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn apply(&self, canvas: &Canvas, encoder: &mut CommandEncoder, input: &Texture, output: &TextureView) {
+        match &self.settings {
+            Some((params, bytes)) => {
+                params.write(canvas, encoder, bytes);
+                self.pass.draw(canvas, encoder, input, output, &[&params.group]);
+            }
+            None => self.pass.draw(canvas, encoder, input, output, &[]),
+        }
+    }
+
+    fn reload(&mut self, canvas: &Canvas, changed: &[String]) -> HermitResult<()> {
+        if self.pass.depends_on(changed) {
+            self.pass.rebuild(canvas, &reload_post_shader(&self.path, canvas)?)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,345 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use wgpu::{BindGroup, BindGroupLayout, Buffer, BufferUsages, CommandEncoder, PipelineLayout, RenderPipeline, ShaderStages, TextureView};
+use wgpu::BindingResource::{Sampler, TextureView as TextureViewResource};
+use crate::error::HermitResult;
+use crate::rendering::buffer::BufferBuilder;
+use crate::rendering::bind_group::{BindGroupBuilder, BindLayout, LayoutEntry};
+use crate::rendering::canvas::Canvas;
+use crate::rendering::pipeline::Pipeline;
+use crate::rendering::post_effects::Tonemap;
+use crate::rendering::preprocessor::{preprocess_source, ShaderDefines};
+use crate::rendering::render_stats::StatsRenderPass;
+use crate::rendering::render_target::{RenderTarget, TargetFormat};
+use crate::rendering::shader::{FragmentEntry, Shader, VertexEntry};
+use crate::util::textures::Texture;
+
+/// A full-screen pass reading the previous image of the [`PostStack`] and writing the next one.
+pub trait PostEffect: Any {
+    fn name(&self) -> &str;
+
+    /// Called whenever the stack recreates its targets, effects owning targets of their own recreate them here.
+    fn resize(&mut self, _canvas: &Canvas, _width: u32, _height: u32) {}
+
+    fn apply(&self, canvas: &Canvas, encoder: &mut CommandEncoder, input: &Texture, output: &TextureView);
+
+    /// Rebuilds the passes whose shaders are among the `changed` paths, called by [`PostStack::reload`].
+    /// Passes that fail to rebuild keep their old pipeline.
+    fn reload(&mut self, _canvas: &Canvas, _changed: &[String]) -> HermitResult<()> {
+        Ok(())
+    }
+}
+
+/// Included in front of every post shader.
+pub const FULLSCREEN_SHADER: &str = "shaders/post/fullscreen.wgsl";
+
+/// Loads a post effect shader, the fragment code in `path` gets the full-screen vertex shader
+/// and the input texture at group 0 from [`FULLSCREEN_SHADER`] included in front of it.
+pub async fn load_post_shader(path: &str, canvas: &Canvas) -> HermitResult<Shader> {
+    let source = format!("#include \"{}\"\n#include \"{}\"\n", FULLSCREEN_SHADER, path);
+    let preprocessed = preprocess_source(path, &source, &ShaderDefines::new()).await?;
+
+    Shader::from_preprocessed(&preprocessed, path, canvas).await
+}
+
+/// Loads the post shader at `path` again, blocking while it compiles.
+/// Like the rest of hot reloading it's only meant for native builds.
+pub fn reload_post_shader(path: &str, canvas: &Canvas) -> HermitResult<Shader> {
+    pollster::block_on(load_post_shader(path, canvas))
+}
+
+/// Pipeline drawing a single triangle over the whole output with the input bound at group 0.
+pub struct FullscreenPass {
+    pipeline: RenderPipeline,
+    input_layout: BindLayout,
+    // Bind groups of the textures drawn from by id, cleared when the canvas size changes
+    // since that's when the targets passed in get recreated
+    input_groups: Mutex<((u32, u32), HashMap<u64, BindGroup>)>,
+    // Kept to rebuild the pipeline when its shader is reloaded
+    pipeline_layout: PipelineLayout,
+    dependencies: Vec<String>,
+    fragment_entry: String,
+    format: wgpu::TextureFormat,
+    label: String,
+}
+
+impl FullscreenPass {
+    // A pass is usually drawn from one or both ping-pong targets
+    const MAX_INPUT_GROUPS: usize = 8;
+
+    /// `extra_layouts` are bound after the input, starting at group 1.
+    pub fn new(canvas: &Canvas, shader: &Shader, fragment_entry: &str, format: wgpu::TextureFormat, extra_layouts: &[&BindGroupLayout], label: &str) -> Self {
+        let input_layout = BindLayout::texture_2d(canvas, Some(label));
+
+        let mut group_layouts = vec![&input_layout.layout];
+        group_layouts.extend_from_slice(extra_layouts);
+        let pipeline_layout = canvas.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts: &group_layouts,
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = Self::build_pipeline(canvas, shader, &pipeline_layout, fragment_entry, format, label);
+
+        Self {
+            pipeline,
+            input_layout,
+            input_groups: Mutex::new(((0, 0), HashMap::new())),
+            pipeline_layout,
+            dependencies: shader.dependencies().to_vec(),
+            fragment_entry: fragment_entry.to_string(),
+            format,
+            label: label.to_string(),
+        }
+    }
+
+    fn build_pipeline(canvas: &Canvas, shader: &Shader, layout: &PipelineLayout, fragment_entry: &str, format: wgpu::TextureFormat, label: &str) -> RenderPipeline {
+        Pipeline::builder(canvas,
+                          VertexEntry::new(&shader.shader_mod, "vs_fullscreen", &[]),
+                          FragmentEntry::new(&shader.shader_mod, fragment_entry),
+        )
+            .label(Some(label))
+            .layout(layout)
+            .target(TargetFormat::new(format))
+            .cull_mode(None)
+            .build()
+    }
+
+    /// Replaces the pipeline with one built from `shader`, keeping the old one if that fails.
+    pub fn rebuild(&mut self, canvas: &Canvas, shader: &Shader) -> HermitResult<()> {
+        let pipeline = self.build_from(canvas, shader)?;
+        self.replace_pipeline(pipeline, shader);
+        Ok(())
+    }
+
+    /// Builds the pipeline from `shader` without using it yet, so passes sharing a shader can all be built
+    /// before any of them is switched over with [`FullscreenPass::replace_pipeline`].
+    pub fn build_from(&self, canvas: &Canvas, shader: &Shader) -> HermitResult<RenderPipeline> {
+        canvas.build_checked(&self.label, || {
+            Self::build_pipeline(canvas, shader, &self.pipeline_layout, &self.fragment_entry, self.format, &self.label)
+        })
+    }
+
+    /// Uses `pipeline`, built by [`FullscreenPass::build_from`] with `shader`.
+    pub fn replace_pipeline(&mut self, pipeline: RenderPipeline, shader: &Shader) {
+        self.pipeline = pipeline;
+        self.dependencies = shader.dependencies().to_vec();
+    }
+
+    /// Whether the shader the pass was built from includes one of the `changed` paths.
+    pub fn depends_on(&self, changed: &[String]) -> bool {
+        changed.iter().any(|path| self.dependencies.contains(path))
+    }
+
+    /// `groups` are bound in the order of the `extra_layouts` the pass was created with.
+    pub fn draw(&self, canvas: &Canvas, encoder: &mut CommandEncoder, input: &Texture, output: &TextureView, groups: &[&BindGroup]) {
+        let mut input_groups = self.input_groups.lock().unwrap();
+        let (size, groups_by_input) = &mut *input_groups;
+        // Textures recreated without a resize would pile up, so the cache is bounded as well
+        if *size != (canvas.config.width, canvas.config.height) || groups_by_input.len() >= Self::MAX_INPUT_GROUPS {
+            *size = (canvas.config.width, canvas.config.height);
+            groups_by_input.clear();
+        }
+        let input_group = groups_by_input.entry(input.id()).or_insert_with(|| {
+            self.input_layout.group(canvas, &[TextureViewResource(&input.view), Sampler(&input.sampler)])
+        });
+
+        let mut render_pass = StatsRenderPass::new(encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(&self.label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        }), &canvas.stats);
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, input_group, &[]);
+        for (index, group) in groups.iter().enumerate() {
+            render_pass.set_bind_group(index as u32 + 1, group, &[]);
+        }
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+/// Uniform buffer holding the settings of an effect, bound at group 1 of its passes.
+pub struct EffectParams {
+    pub buffer: Buffer,
+    pub layout: BindGroupLayout,
+    pub group: BindGroup,
+}
+
+impl EffectParams {
+    pub fn new(canvas: &Canvas, contents: &[u8], label: &str) -> Self {
+        let buffer = BufferBuilder::new(contents, BufferUsages::UNIFORM | BufferUsages::COPY_DST, Some(label), canvas);
+        // The settings differ between effects, so there's no type for BindGroupBuilder::uniform
+        let (layout, group) = BindGroupBuilder::new(canvas)
+            .label(Some(label))
+            .entry(LayoutEntry::new(0, ShaderStages::FRAGMENT, wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            }), buffer.as_entire_binding())
+            .build();
+
+        Self { buffer, layout: layout.layout, group }
+    }
+
+    /// Stages the upload in `encoder` through the [`Canvas::uploader`], ahead of the passes recorded after it.
+    pub fn write(&self, canvas: &Canvas, encoder: &mut CommandEncoder, contents: &[u8]) {
+        canvas.stage_buffer(encoder, &self.buffer, 0, contents);
+    }
+}
+
+const COPY_SHADER: &str = "shaders/post/copy.wgsl";
+
+/// Runs the enabled effects in order after the scene pass, on an HDR canvas starting with a [`Tonemap`].
+/// The scene renders into [`PostStack::scene_target`], every effect but the last one writes into one of two
+/// intermediate targets in the canvas format and the last one writes into the frame.
+pub struct PostStack {
+    effects: Vec<(Box<dyn PostEffect>, bool)>,
+    scene: RenderTarget,
+    targets: [Texture; 2],
+    // Used when no effect is enabled, so the scene still reaches the frame
+    copy: FullscreenPass,
+}
+
+impl PostStack {
+    pub async fn new(canvas: &Canvas) -> HermitResult<Self> {
+        let copy_shader = load_post_shader(COPY_SHADER, canvas).await?;
+        let copy = FullscreenPass::new(canvas, &copy_shader, "fs_main", canvas.config.format, &[], "Post Copy");
+
+        let mut stack = Self {
+            effects: vec![],
+            scene: RenderTarget::for_canvas(canvas, canvas.config.width, canvas.config.height, "Post Scene"),
+            targets: Self::create_targets(canvas),
+            copy,
+        };
+        // The scene is floating point, so it has to be tonemapped before it can be shown
+        if canvas.hdr {
+            stack.push(Tonemap::new(canvas).await?);
+        }
+
+        Ok(stack)
+    }
+
+    fn create_targets(canvas: &Canvas) -> [Texture; 2] {
+        let create = |label| Texture::create_color_texture(
+            &canvas.device,
+            canvas.config.format,
+            canvas.config.width,
+            canvas.config.height,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            label,
+        );
+
+        [create("Post Target A"), create("Post Target B")]
+    }
+
+    pub fn with_effect(mut self, effect: impl PostEffect) -> Self {
+        self.push(effect);
+        self
+    }
+
+    pub fn push(&mut self, effect: impl PostEffect) {
+        self.effects.push((Box::new(effect), true));
+    }
+
+    pub fn insert(&mut self, index: usize, effect: impl PostEffect) {
+        self.effects.insert(index, (Box::new(effect), true));
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn PostEffect>> {
+        let index = self.effects.iter().position(|(effect, _)| effect.name() == name)?;
+        Some(self.effects.remove(index).0)
+    }
+
+    /// Returns false if there is no effect called `name`.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.effects.iter_mut().find(|(effect, _)| effect.name() == name) {
+            Some((_, state)) => {
+                *state = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// First effect of type `T`, used to change its settings.
+    pub fn get_mut<T: PostEffect>(&mut self) -> Option<&mut T> {
+        self.effects.iter_mut()
+            .find_map(|(effect, _)| (effect.as_mut() as &mut dyn Any).downcast_mut::<T>())
+    }
+
+    pub fn get<T: PostEffect>(&self) -> Option<&T> {
+        self.effects.iter()
+            .find_map(|(effect, _)| (effect.as_ref() as &dyn Any).downcast_ref::<T>())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.effects.iter().map(|(effect, _)| effect.name()).collect()
+    }
+
+    /// Where the scene has to be rendered to for the effects to pick it up, it has the [`Canvas::target_format`].
+    pub fn scene_target(&self) -> &RenderTarget {
+        &self.scene
+    }
+
+    pub fn resize(&mut self, canvas: &Canvas) {
+        let (width, height) = (canvas.config.width, canvas.config.height);
+        if width == 0 || height == 0 { return; }
+
+        self.scene.resize(canvas, width, height);
+        self.targets = Self::create_targets(canvas);
+        for (effect, _) in self.effects.iter_mut() {
+            effect.resize(canvas, width, height);
+        }
+    }
+
+    /// Rebuilds the passes of every effect using one of the `changed` shaders, failures are logged
+    /// and leave the effect as it was.
+    pub fn reload(&mut self, canvas: &Canvas, changed: &[String]) {
+        if self.copy.depends_on(changed) {
+            if let Err(error) = reload_post_shader(COPY_SHADER, canvas).and_then(|shader| self.copy.rebuild(canvas, &shader)) {
+                log::error!("{}", error);
+            }
+        }
+
+        for (effect, _) in self.effects.iter_mut() {
+            if let Err(error) = effect.reload(canvas, changed) {
+                log::error!("Cannot reload {}: {}", effect.name(), error);
+            }
+        }
+    }
+
+    /// Runs every enabled effect on the scene target, the result ends up in `output`.
+    /// Effect settings are staged through the [`Canvas::uploader`], so `encoder` has to be submitted
+    /// between [`crate::rendering::upload::Uploader::finish`] and `recall` like the render graph does.
+    pub fn apply(&self, canvas: &Canvas, encoder: &mut CommandEncoder, output: &TextureView) {
+        let enabled: Vec<&dyn PostEffect> = self.effects.iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(effect, _)| effect.as_ref())
+            .collect();
+
+        if enabled.is_empty() {
+            self.copy.draw(canvas, encoder, &self.scene.color, output, &[]);
+            return;
+        }
+
+        let mut input = &self.scene.color;
+        for (index, effect) in enabled.iter().enumerate() {
+            if index == enabled.len() - 1 {
+                effect.apply(canvas, encoder, input, output);
+            } else {
+                let target = &self.targets[index % 2];
+                effect.apply(canvas, encoder, input, &target.view);
+                input = target;
+            }
+        }
+    }
+}
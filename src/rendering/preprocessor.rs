@@ -0,0 +1,499 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
+use crate::error::{HermitError, HermitResult, ShaderDiagnostic};
+use crate::rendering::shader::Shader;
+
+/// Names defined before preprocessing starts, visible to `#ifdef` and substituted into the code like `#define`s.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShaderDefines {
+    defines: BTreeMap<String, String>,
+}
+
+impl ShaderDefines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines `name` without a value, only useful for `#ifdef`.
+    pub fn with(mut self, name: &str) -> Self {
+        self.set(name, "");
+        self
+    }
+
+    /// Defines `name`, every occurrence of it in the code gets replaced by `value`.
+    pub fn with_value(mut self, name: &str, value: impl ToString) -> Self {
+        self.set(name, value);
+        self
+    }
+
+    pub fn set(&mut self, name: &str, value: impl ToString) {
+        self.defines.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.defines.remove(name);
+    }
+
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.defines.contains_key(name)
+    }
+}
+
+/// WGSL with every directive resolved, along with where each of its lines came from.
+#[derive(Clone, Debug)]
+pub struct PreprocessedShader {
+    pub source: String,
+    /// Everything that ended up in the source, starting with the file or label preprocessing started at.
+    pub files: Vec<String>,
+    // File index and line, both for the line of the source at the same index
+    lines: Vec<(usize, u32)>,
+}
+
+impl PreprocessedShader {
+    /// Source that didn't go through the preprocessor, its lines map to themselves in `label`.
+    pub fn unprocessed(label: &str, source: &str) -> Self {
+        Self {
+            source: source.to_string(),
+            files: vec![label.to_string()],
+            lines: (1..=source.lines().count() as u32).map(|line| (0, line)).collect(),
+        }
+    }
+
+    /// File and line, both 1-based, the given line of the preprocessed source came from.
+    pub fn source_location(&self, line: u32) -> Option<(&str, u32)> {
+        let (file, line) = *self.lines.get((line as usize).checked_sub(1)?)?;
+        Some((&self.files[file], line))
+    }
+
+    /// Describes the code at byte `span` of the preprocessed source, with `label` explaining what's wrong with it.
+    pub fn diagnostic(&self, span: Option<Range<usize>>, label: &str, message: &str) -> ShaderDiagnostic {
+        let span = match span.filter(|span| span.end <= self.source.len()) {
+            Some(span) => span,
+            None => return ShaderDiagnostic {
+                file: self.files[0].clone(),
+                line: 0,
+                column: 0,
+                snippet: String::new(),
+                message: message.to_string(),
+            },
+        };
+
+        let line_start = self.source[..span.start].rfind('\n').map_or(0, |newline| newline + 1);
+        let line_end = self.source[span.start..].find('\n').map_or(self.source.len(), |newline| span.start + newline);
+        let line = self.source[..span.start].matches('\n').count() as u32 + 1;
+        let column = self.source[line_start..span.start].chars().count() as u32 + 1;
+        let (file, file_line) = self.source_location(line).unwrap_or((&self.files[0], line));
+
+        // The line as it was compiled, with the span underlined like rustc does
+        let code = &self.source[line_start..line_end];
+        let number = file_line.to_string();
+        let underline = self.source[span.start..span.end.min(line_end)].chars().count().max(1);
+        let mut snippet = format!("{} | {}\n{} | {}{}",
+            number, code, " ".repeat(number.len()), " ".repeat(column as usize - 1), "^".repeat(underline));
+        if !label.is_empty() {
+            snippet.push(' ');
+            snippet.push_str(label);
+        }
+
+        ShaderDiagnostic { file: file.to_string(), line: file_line, column, snippet, message: message.to_string() }
+    }
+
+    /// Rewrites the `wgsl:line:column` locations in a compiler message into `file:line:column` of the original files.
+    pub fn map_message(&self, message: &str) -> String {
+        let mut mapped = String::with_capacity(message.len());
+        let mut rest = message;
+        while let Some(start) = rest.find("wgsl:") {
+            mapped.push_str(&rest[..start]);
+            let after = &rest[start + "wgsl:".len()..];
+            let digits = after.find(|character: char| !character.is_ascii_digit()).unwrap_or(after.len());
+
+            match after[..digits].parse::<u32>().ok().and_then(|line| self.source_location(line)) {
+                Some((file, line)) => {
+                    mapped.push_str(&format!("{}:{}", file, line));
+                    rest = &after[digits..];
+                }
+                None => {
+                    mapped.push_str("wgsl:");
+                    rest = after;
+                }
+            }
+        }
+        mapped.push_str(rest);
+        mapped
+    }
+}
+
+/// Resolves `#include "path"`, `#define NAME [value]`, `#undef NAME`, `#ifdef NAME`, `#ifndef NAME`,
+/// `#else` and `#endif` in the shader at `path`.
+/// Includes are looked up next to the including file first and then relative to the resource root,
+/// every file is only included once so shared structs don't get declared twice.
+pub async fn preprocess(path: &str, defines: &ShaderDefines) -> HermitResult<PreprocessedShader> {
+    let source = Shader::load_source(path).await?;
+    let sources = load_includes(path, &source).await;
+
+    Expander::new(sources, defines).run(path, &source, true)
+}
+
+/// Like [`preprocess`] for source that didn't come from a file, its includes are relative to the resource root.
+pub async fn preprocess_source(label: &str, source: &str, defines: &ShaderDefines) -> HermitResult<PreprocessedShader> {
+    let sources = load_includes("", source).await;
+
+    Expander::new(sources, defines).run(label, source, false)
+}
+
+// Loads everything the source could include, whether or not it's inside an active block. Files that
+// fail to load are left out, which only matters if the expander actually reaches their include.
+async fn load_includes(path: &str, source: &str) -> HashMap<String, String> {
+    let mut sources = HashMap::new();
+    let mut queue: Vec<(String, String)> = include_paths(source).into_iter().map(|include| (path.to_string(), include)).collect();
+    let mut attempted = HashSet::new();
+
+    while let Some((from, include)) = queue.pop() {
+        for candidate in include_candidates(&from, &include) {
+            if sources.contains_key(&candidate) { break; }
+            if !attempted.insert(candidate.clone()) { continue; }
+
+            if let Ok(included) = Shader::load_source(&candidate).await {
+                queue.extend(include_paths(&included).into_iter().map(|next| (candidate.clone(), next)));
+                sources.insert(candidate, included);
+                break;
+            }
+        }
+    }
+
+    sources
+}
+
+fn include_paths(source: &str) -> Vec<String> {
+    source.lines()
+        .filter_map(|line| parse_directive(line))
+        .filter(|(directive, _)| *directive == "include")
+        .filter_map(|(_, argument)| parse_include(argument))
+        .collect()
+}
+
+fn parse_directive(line: &str) -> Option<(&str, &str)> {
+    let directive = line.trim().strip_prefix('#')?;
+    let (name, argument) = directive.split_once(char::is_whitespace).unwrap_or((directive, ""));
+    Some((name, argument.trim()))
+}
+
+fn parse_include(argument: &str) -> Option<String> {
+    let path = argument.strip_prefix('"')?.strip_suffix('"')?;
+    (!path.is_empty()).then(|| path.to_string())
+}
+
+// Next to the including file, then relative to the resource root
+fn include_candidates(from: &str, include: &str) -> Vec<String> {
+    let mut candidates = vec![];
+    if let Some((directory, _)) = from.rsplit_once('/') {
+        candidates.push(normalize(&format!("{}/{}", directory, include)));
+    }
+    candidates.push(normalize(include));
+    candidates.dedup();
+    candidates
+}
+
+fn normalize(path: &str) -> String {
+    let mut parts: Vec<&str> = vec![];
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => { parts.pop(); }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+struct Conditional {
+    // Whether the enclosing block is active
+    parent: bool,
+    condition: bool,
+    in_else: bool,
+}
+
+impl Conditional {
+    fn active(&self) -> bool {
+        self.parent && (self.condition != self.in_else)
+    }
+}
+
+struct Expander {
+    sources: HashMap<String, String>,
+    defines: HashMap<String, String>,
+    included: HashSet<String>,
+    // Files currently being expanded, to report include cycles
+    stack: Vec<String>,
+    output: String,
+    files: Vec<String>,
+    lines: Vec<(usize, u32)>,
+}
+
+impl Expander {
+    fn new(sources: HashMap<String, String>, defines: &ShaderDefines) -> Self {
+        Self {
+            sources,
+            defines: defines.defines.iter().map(|(name, value)| (name.clone(), value.clone())).collect(),
+            included: HashSet::new(),
+            stack: vec![],
+            output: String::new(),
+            files: vec![],
+            lines: vec![],
+        }
+    }
+
+    fn run(mut self, label: &str, source: &str, is_file: bool) -> HermitResult<PreprocessedShader> {
+        if is_file {
+            self.included.insert(label.to_string());
+        }
+        self.expand(label, if is_file { label } else { "" }, source)?;
+
+        Ok(PreprocessedShader { source: self.output, files: self.files, lines: self.lines })
+    }
+
+    fn error(path: &str, line: u32, message: impl ToString) -> HermitError {
+        HermitError::ShaderPreprocessFailed { path: path.to_string(), line, message: message.to_string() }
+    }
+
+    // `path` is what includes are resolved relative to, empty for source that isn't a file
+    fn expand(&mut self, label: &str, path: &str, source: &str) -> HermitResult<()> {
+        self.stack.push(path.to_string());
+        let file = self.files.len();
+        self.files.push(label.to_string());
+
+        let mut conditionals: Vec<Conditional> = vec![];
+        let mut last_line = 0;
+        for (index, line) in source.lines().enumerate() {
+            let number = index as u32 + 1;
+            last_line = number;
+            let active = conditionals.last().is_none_or(Conditional::active);
+
+            let (directive, argument) = match parse_directive(line) {
+                Some(directive) => directive,
+                None => {
+                    if active {
+                        let substituted = self.substitute(line);
+                        self.output.push_str(&substituted);
+                        self.output.push('\n');
+                        self.lines.push((file, number));
+                    }
+                    continue;
+                }
+            };
+
+            match directive {
+                "ifdef" | "ifndef" => {
+                    let name = Self::name(argument).ok_or_else(|| Self::error(label, number, format!("#{} needs a name", directive)))?;
+                    let condition = self.defines.contains_key(name) == (directive == "ifdef");
+                    conditionals.push(Conditional { parent: active, condition, in_else: false });
+                }
+                "else" => match conditionals.last_mut() {
+                    Some(conditional) if !conditional.in_else => conditional.in_else = true,
+                    Some(_) => return Err(Self::error(label, number, "second #else in the same block")),
+                    None => return Err(Self::error(label, number, "#else without #ifdef")),
+                },
+                "endif" => {
+                    if conditionals.pop().is_none() {
+                        return Err(Self::error(label, number, "#endif without #ifdef"));
+                    }
+                }
+                _ if !active => {}
+                "define" => {
+                    let (name, value) = argument.split_once(char::is_whitespace).unwrap_or((argument, ""));
+                    let name = Self::name(name).ok_or_else(|| Self::error(label, number, "#define needs a name"))?;
+                    self.defines.insert(name.to_string(), value.trim().to_string());
+                }
+                "undef" => {
+                    let name = Self::name(argument).ok_or_else(|| Self::error(label, number, "#undef needs a name"))?;
+                    self.defines.remove(name);
+                }
+                "include" => {
+                    let include = parse_include(argument).ok_or_else(|| Self::error(label, number, "expected #include \"path\""))?;
+                    self.include(label, path, number, &include)?;
+                }
+                _ => return Err(Self::error(label, number, format!("unknown directive #{}", directive))),
+            }
+        }
+
+        if !conditionals.is_empty() {
+            return Err(Self::error(label, last_line, "#ifdef without #endif"));
+        }
+        self.stack.pop();
+        Ok(())
+    }
+
+    fn include(&mut self, label: &str, path: &str, line: u32, include: &str) -> HermitResult<()> {
+        let candidates = include_candidates(path, include);
+        let resolved = match candidates.iter().find(|candidate| self.sources.contains_key(*candidate)) {
+            Some(resolved) => resolved.clone(),
+            None => return Err(Self::error(label, line, format!("cannot find included file {}", include))),
+        };
+
+        if self.stack.contains(&resolved) {
+            return Err(Self::error(label, line, format!("{} includes itself", resolved)));
+        }
+        if !self.included.insert(resolved.clone()) {
+            return Ok(());
+        }
+
+        let source = self.sources[&resolved].clone();
+        self.expand(&resolved, &resolved, &source)
+    }
+
+    fn name(argument: &str) -> Option<&str> {
+        let name = argument.split_whitespace().next()?;
+        let valid = name.chars().next().is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+            && name.chars().all(|character| character.is_ascii_alphanumeric() || character == '_');
+        valid.then_some(name)
+    }
+
+    // Replaces identifiers that are defined with a value, leaving comments alone
+    fn substitute(&self, line: &str) -> String {
+        if !self.defines.values().any(|value| !value.is_empty()) {
+            return line.to_string();
+        }
+
+        let (code, comment) = match line.find("//") {
+            Some(start) => line.split_at(start),
+            None => (line, ""),
+        };
+
+        let mut substituted = String::with_capacity(line.len());
+        let mut identifier = String::new();
+        for character in code.chars() {
+            if character.is_ascii_alphanumeric() || character == '_' {
+                identifier.push(character);
+            } else {
+                self.flush_identifier(&mut identifier, &mut substituted);
+                substituted.push(character);
+            }
+        }
+        self.flush_identifier(&mut identifier, &mut substituted);
+
+        substituted.push_str(comment);
+        substituted
+    }
+
+    fn flush_identifier(&self, identifier: &mut String, output: &mut String) {
+        // Numbers like 1u are collected too, they never match a define
+        match self.defines.get(identifier.as_str()) {
+            Some(value) if !value.is_empty() => output.push_str(value),
+            _ => output.push_str(identifier),
+        }
+        identifier.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(source: &str, includes: &[(&str, &str)], defines: &ShaderDefines) -> HermitResult<PreprocessedShader> {
+        let sources = includes.iter().map(|(path, source)| (path.to_string(), source.to_string())).collect();
+        Expander::new(sources, defines).run("shaders/main.wgsl", source, true)
+    }
+
+    fn error_line(result: HermitResult<PreprocessedShader>) -> (String, u32, String) {
+        match result {
+            Err(HermitError::ShaderPreprocessFailed { path, line, message }) => (path, line, message),
+            other => panic!("expected a preprocessor error, got {:?}", other.map(|shader| shader.source)),
+        }
+    }
+
+    #[test]
+    fn includes_next_to_the_file_before_the_root() {
+        let shader = expand(
+            "#include \"common.wgsl\"\nfn main() {}",
+            &[("shaders/common.wgsl", "struct Local {}"), ("common.wgsl", "struct Root {}")],
+            &ShaderDefines::new(),
+        ).unwrap();
+
+        assert_eq!(shader.source, "struct Local {}\nfn main() {}\n");
+        assert_eq!(shader.files, ["shaders/main.wgsl", "shaders/common.wgsl"]);
+    }
+
+    #[test]
+    fn includes_every_file_once() {
+        let shader = expand(
+            "#include \"a.wgsl\"\n#include \"b.wgsl\"\n#include \"a.wgsl\"",
+            &[("shaders/a.wgsl", "#include \"common.wgsl\"\nstruct A {}"), ("shaders/b.wgsl", "#include \"common.wgsl\"\nstruct B {}"), ("shaders/common.wgsl", "struct Common {}")],
+            &ShaderDefines::new(),
+        ).unwrap();
+
+        assert_eq!(shader.source, "struct Common {}\nstruct A {}\nstruct B {}\n");
+    }
+
+    #[test]
+    fn nested_conditionals() {
+        let source = "#ifdef OUTER\n#ifndef INNER\nouter only\n#else\nboth\n#endif\n#else\nneither\n#ifdef INNER\ninner only\n#endif\n#endif";
+
+        let expanded = |defines: ShaderDefines| expand(source, &[], &defines).unwrap().source;
+        assert_eq!(expanded(ShaderDefines::new()), "neither\n");
+        assert_eq!(expanded(ShaderDefines::new().with("OUTER")), "outer only\n");
+        assert_eq!(expanded(ShaderDefines::new().with("OUTER").with("INNER")), "both\n");
+        assert_eq!(expanded(ShaderDefines::new().with("INNER")), "neither\ninner only\n");
+    }
+
+    #[test]
+    fn directives_in_inactive_blocks_are_skipped() {
+        let shader = expand(
+            "#ifdef MISSING\n#define SIZE 2\n#include \"missing.wgsl\"\n#endif\nSIZE",
+            &[],
+            &ShaderDefines::new(),
+        ).unwrap();
+
+        assert_eq!(shader.source, "SIZE\n");
+    }
+
+    #[test]
+    fn substitutes_defines_outside_of_comments() {
+        let shader = expand(
+            "#define WORKGROUP_SIZE 64\n@workgroup_size(WORKGROUP_SIZE, SAMPLES) // WORKGROUP_SIZE\nWORKGROUP_SIZE_X\n#undef WORKGROUP_SIZE\nWORKGROUP_SIZE",
+            &[],
+            &ShaderDefines::new().with_value("SAMPLES", 4),
+        ).unwrap();
+
+        assert_eq!(shader.source, "@workgroup_size(64, 4) // WORKGROUP_SIZE\nWORKGROUP_SIZE_X\nWORKGROUP_SIZE\n");
+    }
+
+    #[test]
+    fn maps_lines_back_to_their_files() {
+        let shader = expand(
+            "// main\n#include \"common.wgsl\"\n#ifdef MISSING\nskipped\n#endif\nfn main() {}",
+            &[("shaders/common.wgsl", "// common\nstruct Common {}")],
+            &ShaderDefines::new(),
+        ).unwrap();
+
+        assert_eq!(shader.source_location(1), Some(("shaders/main.wgsl", 1)));
+        assert_eq!(shader.source_location(2), Some(("shaders/common.wgsl", 1)));
+        assert_eq!(shader.source_location(3), Some(("shaders/common.wgsl", 2)));
+        assert_eq!(shader.source_location(4), Some(("shaders/main.wgsl", 6)));
+        assert_eq!(shader.source_location(5), None);
+        assert_eq!(shader.map_message("error at wgsl:3:8"), "error at shaders/common.wgsl:2:8");
+    }
+
+    #[test]
+    fn reports_where_directives_are_wrong() {
+        let none = ShaderDefines::new();
+        assert_eq!(error_line(expand("#include \"missing.wgsl\"", &[], &none)).1, 1);
+        assert_eq!(error_line(expand("#ifdef A\n", &[], &none)).2, "#ifdef without #endif");
+        assert_eq!(error_line(expand("code\n#endif", &[], &none)), ("shaders/main.wgsl".to_string(), 2, "#endif without #ifdef".to_string()));
+        assert_eq!(error_line(expand("#else", &[], &none)).2, "#else without #ifdef");
+        assert_eq!(error_line(expand("#ifdef A\n#else\n#else\n#endif", &[], &none)).1, 3);
+        assert_eq!(error_line(expand("#ifdef 1A\n#endif", &[], &none)).2, "#ifdef needs a name");
+        assert_eq!(error_line(expand("#pragma once", &[], &none)).2, "unknown directive #pragma");
+        assert_eq!(error_line(expand("#include missing.wgsl", &[], &none)).2, "expected #include \"path\"");
+    }
+
+    #[test]
+    fn reports_include_cycles_in_the_including_file() {
+        let result = expand(
+            "#include \"a.wgsl\"",
+            &[("shaders/a.wgsl", "#include \"b.wgsl\""), ("shaders/b.wgsl", "\n#include \"a.wgsl\"")],
+            &ShaderDefines::new(),
+        );
+
+        assert_eq!(error_line(result), ("shaders/b.wgsl".to_string(), 2, "shaders/a.wgsl includes itself".to_string()));
+    }
+}
@@ -0,0 +1,410 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use wgpu::{Buffer, CommandEncoder, QuerySet};
+use crate::rendering::canvas::Canvas;
+
+/// A timed scope, `start` is relative to when the profiler was created.
+#[derive(Clone, Debug)]
+pub struct ScopeTiming {
+    pub name: String,
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FrameProfile {
+    pub frame: u64,
+    pub cpu: Vec<ScopeTiming>,
+    // Filled in a few frames later, once the gpu is done with the frame
+    pub gpu: Vec<ScopeTiming>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ScopeStats {
+    pub last: Duration,
+    pub average: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+const MAX_GPU_SCOPES: u32 = 64;
+// Frames whose timestamps can be waiting for readback at the same time
+const READBACK_BUFFERS: usize = 3;
+
+struct Readback {
+    buffer: Buffer,
+    mapped: Arc<AtomicBool>,
+    in_flight: bool,
+    frame: u64,
+    // Cpu time the frame started at, gpu scopes get placed relative to it
+    frame_start: Duration,
+    scopes: Vec<(String, u32)>,
+}
+
+struct GpuTimer {
+    query_set: QuerySet,
+    resolve_buffer: Buffer,
+    readbacks: Vec<Readback>,
+    // Nanoseconds per timestamp tick
+    period: f32,
+    next_query: u32,
+    open: Vec<(String, u32)>,
+    scopes: Vec<(String, u32)>,
+    // Readback waiting for the frame's submission before it can be mapped
+    pending: Option<usize>,
+}
+
+impl GpuTimer {
+    fn new(canvas: &Canvas) -> Self {
+        let query_set = canvas.device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Profiler Timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: MAX_GPU_SCOPES * 2,
+        });
+        let size = (MAX_GPU_SCOPES * 2) as wgpu::BufferAddress * 8;
+        let resolve_buffer = canvas.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Profiler Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readbacks = (0..READBACK_BUFFERS).map(|_| Readback {
+            buffer: canvas.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Profiler Readback Buffer"),
+                size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            mapped: Arc::new(AtomicBool::new(false)),
+            in_flight: false,
+            frame: 0,
+            frame_start: Duration::ZERO,
+            scopes: vec![],
+        }).collect();
+
+        Self {
+            query_set,
+            resolve_buffer,
+            readbacks,
+            period: canvas.queue.get_timestamp_period(),
+            next_query: 0,
+            open: vec![],
+            scopes: vec![],
+            pending: None,
+        }
+    }
+}
+
+/// Records cpu time of scopes like `update` and `render` and, if the device supports
+/// [`wgpu::Features::TIMESTAMP_QUERY`], gpu time of scopes recorded into command encoders.
+/// Gpu results are read back asynchronously and show up a few frames after they were recorded.
+pub struct Profiler {
+    start: Instant,
+    frame: u64,
+    frame_start: Duration,
+    history_len: usize,
+    cpu_open: Vec<(String, Instant)>,
+    cpu_history: HashMap<String, VecDeque<Duration>>,
+    gpu_history: HashMap<String, VecDeque<Duration>>,
+    frames: VecDeque<FrameProfile>,
+    gpu: Option<GpuTimer>,
+}
+
+impl Profiler {
+    pub fn new(canvas: &Canvas) -> Self {
+        let gpu = if canvas.device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            Some(GpuTimer::new(canvas))
+        } else {
+            log::info!("Timestamp queries are not supported, only profiling cpu time");
+            None
+        };
+
+        Self {
+            start: Instant::now(),
+            frame: 0,
+            frame_start: Duration::ZERO,
+            history_len: 120,
+            cpu_open: vec![],
+            cpu_history: HashMap::new(),
+            gpu_history: HashMap::new(),
+            frames: VecDeque::new(),
+            gpu,
+        }
+    }
+
+    /// Number of frames kept per scope and for trace export.
+    pub fn with_history_len(mut self, history_len: usize) -> Self {
+        self.history_len = history_len.max(1);
+        self
+    }
+
+    pub fn has_gpu_timing(&self) -> bool {
+        self.gpu.is_some()
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Starts a new frame and collects gpu timings that became available since the last one.
+    pub fn begin_frame(&mut self, canvas: &Canvas) {
+        self.frame += 1;
+        self.frame_start = self.start.elapsed();
+        self.cpu_open.clear();
+
+        self.frames.push_back(FrameProfile { frame: self.frame, ..Default::default() });
+        while self.frames.len() > self.history_len {
+            self.frames.pop_front();
+        }
+
+        if self.gpu.is_some() {
+            canvas.device.poll(wgpu::Maintain::Poll);
+            self.collect_gpu();
+        }
+        if let Some(gpu) = &mut self.gpu {
+            gpu.next_query = 0;
+            gpu.open.clear();
+            gpu.scopes.clear();
+        }
+    }
+
+    pub fn begin_scope(&mut self, name: &str) {
+        self.cpu_open.push((name.to_string(), Instant::now()));
+    }
+
+    /// Ends the innermost open scope.
+    pub fn end_scope(&mut self) {
+        let (name, started) = match self.cpu_open.pop() {
+            Some(scope) => scope,
+            None => return,
+        };
+        let timing = ScopeTiming {
+            start: started.duration_since(self.start),
+            duration: started.elapsed(),
+            name,
+        };
+
+        Self::record(&mut self.cpu_history, self.history_len, &timing);
+        if let Some(profile) = self.frames.back_mut() {
+            profile.cpu.push(timing);
+        }
+    }
+
+    /// Starts timing the gpu work recorded into `encoder` from here on, does nothing without timestamp support.
+    pub fn begin_gpu_scope(&mut self, encoder: &mut CommandEncoder, name: &str) {
+        let gpu = match &mut self.gpu {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        if gpu.next_query + 2 > MAX_GPU_SCOPES * 2 {
+            log::debug!("More than {} gpu scopes in one frame, ignoring {}", MAX_GPU_SCOPES, name);
+            // Still pushed so the matching end_gpu_scope doesn't close the enclosing scope
+            gpu.open.push((name.to_string(), u32::MAX));
+            return;
+        }
+
+        encoder.write_timestamp(&gpu.query_set, gpu.next_query);
+        gpu.open.push((name.to_string(), gpu.next_query));
+        gpu.next_query += 2;
+    }
+
+    pub fn end_gpu_scope(&mut self, encoder: &mut CommandEncoder) {
+        let gpu = match &mut self.gpu {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        if let Some((name, query)) = gpu.open.pop().filter(|(_, query)| *query != u32::MAX) {
+            encoder.write_timestamp(&gpu.query_set, query + 1);
+            gpu.scopes.push((name, query));
+        }
+    }
+
+    /// Copies this frame's timestamps into a readback buffer, record it into the last encoder of the frame.
+    /// [`Profiler::end_frame`] has to be called once that encoder was submitted.
+    pub fn resolve(&mut self, encoder: &mut CommandEncoder) {
+        let gpu = match &mut self.gpu {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        // Every resolved query has to be written, so scopes left open end here
+        while let Some((name, query)) = gpu.open.pop() {
+            if query != u32::MAX {
+                encoder.write_timestamp(&gpu.query_set, query + 1);
+                gpu.scopes.push((name, query));
+            }
+        }
+        if gpu.scopes.is_empty() || gpu.pending.is_some() { return; }
+
+        let index = match gpu.readbacks.iter().position(|readback| !readback.in_flight) {
+            Some(index) => index,
+            None => {
+                log::debug!("All profiler readback buffers are in use, dropping gpu timings of frame {}", self.frame);
+                return;
+            }
+        };
+
+        encoder.resolve_query_set(&gpu.query_set, 0..gpu.next_query, &gpu.resolve_buffer, 0);
+        let readback = &mut gpu.readbacks[index];
+        encoder.copy_buffer_to_buffer(&gpu.resolve_buffer, 0, &readback.buffer, 0, gpu.next_query as wgpu::BufferAddress * 8);
+
+        readback.in_flight = true;
+        readback.frame = self.frame;
+        readback.frame_start = self.frame_start;
+        readback.scopes = std::mem::take(&mut gpu.scopes);
+        gpu.pending = Some(index);
+    }
+
+    /// Starts mapping the resolved timestamps, call after submitting the encoder [`Profiler::resolve`] was recorded into.
+    pub fn end_frame(&mut self) {
+        let gpu = match &mut self.gpu {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        if let Some(index) = gpu.pending.take() {
+            let readback = &gpu.readbacks[index];
+            let mapped = readback.mapped.clone();
+            readback.buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+                mapped.store(result.is_ok(), Ordering::Release);
+            });
+        }
+    }
+
+    fn collect_gpu(&mut self) {
+        let gpu = match &mut self.gpu {
+            Some(gpu) => gpu,
+            None => return,
+        };
+
+        for readback in gpu.readbacks.iter_mut() {
+            if !readback.in_flight || !readback.mapped.load(Ordering::Acquire) { continue; }
+
+            let timestamps: Vec<u64> = {
+                let data = readback.buffer.slice(..).get_mapped_range();
+                bytemuck::cast_slice(&data).to_vec()
+            };
+            readback.buffer.unmap();
+            readback.mapped.store(false, Ordering::Release);
+            readback.in_flight = false;
+
+            let base = readback.scopes.iter().map(|(_, query)| timestamps[*query as usize]).min().unwrap_or(0);
+            let to_duration = |ticks: u64| Duration::from_nanos((ticks as f64 * gpu.period as f64) as u64);
+            for (name, query) in readback.scopes.drain(..) {
+                let begin = timestamps[query as usize];
+                let end = timestamps[query as usize + 1];
+                let timing = ScopeTiming {
+                    name,
+                    start: readback.frame_start + to_duration(begin.saturating_sub(base)),
+                    duration: to_duration(end.saturating_sub(begin)),
+                };
+
+                Self::record(&mut self.gpu_history, self.history_len, &timing);
+                if let Some(profile) = self.frames.iter_mut().find(|profile| profile.frame == readback.frame) {
+                    profile.gpu.push(timing);
+                }
+            }
+        }
+    }
+
+    fn record(history: &mut HashMap<String, VecDeque<Duration>>, history_len: usize, timing: &ScopeTiming) {
+        let durations = history.entry(timing.name.clone()).or_default();
+        durations.push_back(timing.duration);
+        while durations.len() > history_len {
+            durations.pop_front();
+        }
+    }
+
+    fn stats(durations: &VecDeque<Duration>) -> Option<ScopeStats> {
+        Some(ScopeStats {
+            last: *durations.back()?,
+            average: durations.iter().sum::<Duration>() / durations.len() as u32,
+            min: *durations.iter().min()?,
+            max: *durations.iter().max()?,
+        })
+    }
+
+    /// Rolling cpu durations of `scope`, oldest first.
+    pub fn cpu_history(&self, scope: &str) -> Option<&VecDeque<Duration>> {
+        self.cpu_history.get(scope)
+    }
+
+    pub fn gpu_history(&self, scope: &str) -> Option<&VecDeque<Duration>> {
+        self.gpu_history.get(scope)
+    }
+
+    pub fn cpu_stats(&self, scope: &str) -> Option<ScopeStats> {
+        Self::stats(self.cpu_history.get(scope)?)
+    }
+
+    pub fn gpu_stats(&self, scope: &str) -> Option<ScopeStats> {
+        Self::stats(self.gpu_history.get(scope)?)
+    }
+
+    /// Profiles of the last frames, oldest first.
+    pub fn frames(&self) -> &VecDeque<FrameProfile> {
+        &self.frames
+    }
+
+    /// One line per scope with its average, min and max over the history.
+    pub fn summary(&self) -> String {
+        let mut summary = format!("Frame {}", self.frame);
+        for (kind, history) in [("cpu", &self.cpu_history), ("gpu", &self.gpu_history)] {
+            let mut names: Vec<&String> = history.keys().collect();
+            names.sort();
+            for name in names {
+                if let Some(stats) = Self::stats(&history[name]) {
+                    let _ = write!(summary, "\n  {} {}: avg {:?}, min {:?}, max {:?}", kind, name, stats.average, stats.min, stats.max);
+                }
+            }
+        }
+        summary
+    }
+
+    /// The kept frames in the Chrome trace event format, viewable in chrome://tracing or Perfetto.
+    pub fn chrome_trace(&self) -> String {
+        let mut events = vec![];
+        for profile in self.frames.iter() {
+            for (thread, timings) in [(0, &profile.cpu), (1, &profile.gpu)] {
+                for timing in timings {
+                    events.push(format!(
+                        "{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":0,\"tid\":{},\"args\":{{\"frame\":{}}}}}",
+                        escape_json(&timing.name),
+                        if thread == 0 { "cpu" } else { "gpu" },
+                        timing.start.as_micros(),
+                        timing.duration.as_micros(),
+                        thread,
+                        profile.frame,
+                    ));
+                }
+            }
+        }
+
+        let names = "{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":0,\"args\":{\"name\":\"CPU\"}},\
+                     {\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":1,\"args\":{\"name\":\"GPU\"}}";
+        if events.is_empty() {
+            format!("{{\"traceEvents\":[{}]}}", names)
+        } else {
+            format!("{{\"traceEvents\":[{},{}]}}", names, events.join(","))
+        }
+    }
+
+    pub fn write_chrome_trace(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.chrome_trace())
+    }
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            character if character.is_control() => { let _ = write!(escaped, "\\u{:04x}", character as u32); }
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
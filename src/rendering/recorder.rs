@@ -0,0 +1,155 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+use crate::error::{HermitError, HermitResult};
+use crate::rendering::canvas::{Canvas, CanvasFrame};
+use crate::rendering::frame_capture::FrameCapture;
+
+/// Where recorded frames end up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecordTarget {
+    /// Numbered `frame-000000.png` files in the directory, which gets created if missing.
+    Png(PathBuf),
+    /// Tightly packed rgba8 frames written back to back, e.g. for `ffmpeg -f rawvideo -pix_fmt rgba -s WxH -i -`.
+    RawStdout,
+}
+
+/// Captures a fixed number of consecutive frames.
+/// While recording, [`Recorder::timestep`] has to drive the simulation so every frame advances it by
+/// the same amount, which keeps the capture deterministic even when rendering is slower than real time.
+pub struct Recorder {
+    pub key: VirtualKeyCode,
+    /// Frames recorded when starting through [`Recorder::handle_input`].
+    pub default_frames: u32,
+    pub default_fps: u32,
+    target: Option<RecordTarget>,
+    remaining: u32,
+    recorded: u32,
+    timestep: Duration,
+    capture: FrameCapture,
+}
+
+impl Recorder {
+    pub const DEFAULT_KEY: VirtualKeyCode = VirtualKeyCode::F11;
+
+    pub async fn new(canvas: &Canvas) -> HermitResult<Self> {
+        Ok(Self {
+            key: Self::DEFAULT_KEY,
+            default_frames: 300,
+            default_fps: 60,
+            target: None,
+            remaining: 0,
+            recorded: 0,
+            timestep: Duration::ZERO,
+            capture: FrameCapture::new(canvas).await?,
+        })
+    }
+
+    /// Records the next `frames` frames as if the engine was running at `fps`.
+    pub fn start(&mut self, target: RecordTarget, frames: u32, fps: u32) -> HermitResult<()> {
+        if frames == 0 { return Ok(()); }
+
+        if let RecordTarget::Png(directory) = &target {
+            std::fs::create_dir_all(directory)
+                .map_err(|error| HermitError::CaptureFailed(format!("{}: {}", directory.display(), error)))?;
+        }
+
+        log::info!("Recording {} frames at {} fps to {:?}", frames, fps, target);
+        self.target = Some(target);
+        self.remaining = frames;
+        self.recorded = 0;
+        self.timestep = Duration::from_secs_f64(1.0 / fps.max(1) as f64);
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        if self.target.take().is_some() {
+            log::info!("Recorded {} frames", self.recorded);
+        }
+        self.remaining = 0;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.target.is_some()
+    }
+
+    /// Frames written since the last start.
+    pub fn recorded(&self) -> u32 {
+        self.recorded
+    }
+
+    /// The fixed timestep to simulate with while recording, `None` otherwise.
+    pub fn timestep(&self) -> Option<Duration> {
+        self.target.as_ref().map(|_| self.timestep)
+    }
+
+    /// `recording-<unix millis>` in the working directory.
+    pub fn timestamped_directory() -> PathBuf {
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_millis()).unwrap_or(0);
+        PathBuf::from(format!("recording-{}", millis))
+    }
+
+    /// Starts or stops a PNG recording when [`Recorder::key`] gets pressed, returns whether the event was used.
+    pub fn handle_input(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(key),
+                    ..
+                },
+                ..
+            } if *key == self.key => {
+                if self.is_recording() {
+                    self.stop();
+                } else if let Err(error) = self.start(RecordTarget::Png(Self::timestamped_directory()), self.default_frames, self.default_fps) {
+                    log::error!("{}", error);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Makes `frame` render into a readable texture while recording, call right after [`Canvas::frame`].
+    pub fn redirect(&mut self, canvas: &Canvas, frame: &mut CanvasFrame) {
+        if self.is_recording() {
+            self.capture.redirect(canvas, frame);
+        }
+    }
+
+    /// Writes the frame if it was recorded, call before [`CanvasFrame::present`].
+    /// Stops recording after the last frame or when writing fails.
+    pub fn finish(&mut self, canvas: &Canvas, frame: &mut CanvasFrame) -> HermitResult<()> {
+        let image = match self.capture.finish(canvas, frame)? {
+            Some(image) => image,
+            None => return Ok(()),
+        };
+
+        let written = match &self.target {
+            Some(RecordTarget::Png(directory)) => {
+                let path = directory.join(format!("frame-{:06}.png", self.recorded));
+                image.save(&path).map_err(|error| HermitError::CaptureFailed(format!("{}: {}", path.display(), error)))
+            }
+            Some(RecordTarget::RawStdout) => {
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(image.as_raw())
+                    .and_then(|_| stdout.flush())
+                    .map_err(|error| HermitError::CaptureFailed(format!("stdout: {}", error)))
+            }
+            None => Ok(()),
+        };
+        if written.is_err() {
+            self.stop();
+            return written;
+        }
+
+        self.recorded += 1;
+        self.remaining = self.remaining.saturating_sub(1);
+        if self.remaining == 0 {
+            self.stop();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,583 @@
+use std::num::NonZeroU64;
+use naga::valid::{Capabilities, GlobalUse, ValidationFlags, Validator};
+use wgpu::{BindGroupLayout, BindGroupLayoutEntry, BindingType, BufferAddress, ShaderStages, TextureFormat, VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode};
+use crate::error::{HermitError, HermitResult};
+use crate::rendering::preprocessor::PreprocessedShader;
+use crate::rendering::canvas::Canvas;
+
+/// A resource the shader declares at `@group(group) @binding(binding)`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub group: u32,
+    pub binding: u32,
+    pub name: String,
+    pub ty: BindingType,
+    /// Stages of the entry points that use it, empty if none does.
+    pub visibility: ShaderStages,
+}
+
+impl ReflectedBinding {
+    pub fn layout_entry(&self) -> BindGroupLayoutEntry {
+        BindGroupLayoutEntry {
+            binding: self.binding,
+            visibility: self.visibility,
+            ty: self.ty,
+            count: None,
+        }
+    }
+}
+
+/// A `@location` input of a vertex entry point, with the format matching its WGSL type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReflectedVertexInput {
+    pub location: u32,
+    pub name: String,
+    pub format: VertexFormat,
+}
+
+#[derive(Clone, Debug)]
+pub struct ReflectedEntryPoint {
+    pub name: String,
+    pub stage: ShaderStages,
+    /// Empty for anything but vertex entry points.
+    pub vertex_inputs: Vec<ReflectedVertexInput>,
+    // Group and binding of everything the entry point uses, including through the functions it calls
+    bindings: Vec<(u32, u32)>,
+}
+
+/// Every input of a vertex entry point packed into one interleaved buffer, in location order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReflectedVertexLayout {
+    pub array_stride: BufferAddress,
+    pub attributes: Vec<VertexAttribute>,
+}
+
+impl ReflectedVertexLayout {
+    pub fn layout(&self, step_mode: VertexStepMode) -> VertexBufferLayout<'_> {
+        VertexBufferLayout {
+            array_stride: self.array_stride,
+            step_mode,
+            attributes: &self.attributes,
+        }
+    }
+}
+
+/// What a shader expects from the pipelines built from it, read from the WGSL with naga.
+/// Can create the bind group and vertex layouts instead of writing them by hand,
+/// or check hand-written ones before wgpu rejects them with a less specific error.
+#[derive(Clone, Debug)]
+pub struct ShaderReflection {
+    label: String,
+    bindings: Vec<ReflectedBinding>,
+    entry_points: Vec<ReflectedEntryPoint>,
+}
+
+impl ShaderReflection {
+    pub fn new(source: &str, label: &str, features: wgpu::Features) -> HermitResult<Self> {
+        Self::from_preprocessed(&PreprocessedShader::unprocessed(label, source), label, features)
+    }
+
+    /// Parses and validates the WGSL, failing with a [`HermitError::ShaderInvalid`] pointing into the
+    /// files the source was assembled from. Only what `features`, those of the device, allow is accepted.
+    pub fn from_preprocessed(preprocessed: &PreprocessedShader, label: &str, features: wgpu::Features) -> HermitResult<Self> {
+        let module = naga::front::wgsl::parse_str(&preprocessed.source).map_err(|error| {
+            let (span, span_label) = error.labels().next().map_or((None, ""), |(span, span_label)| (Some(span), span_label));
+            HermitError::ShaderInvalid(preprocessed.diagnostic(span, span_label, error.message()))
+        })?;
+        // The last span is the most specific one, e.g. the expression inside the invalid function
+        let info = Validator::new(ValidationFlags::all(), capabilities(features)).validate(&module).map_err(|error| {
+            let (span, span_label) = error.spans().last().map_or((None, ""), |(span, span_label)| (span.to_range(), span_label.as_str()));
+            HermitError::ShaderInvalid(preprocessed.diagnostic(span, span_label, &error_chain(&error)))
+        })?;
+
+        let mut bindings = vec![];
+        let mut entry_points: Vec<ReflectedEntryPoint> = module.entry_points.iter()
+            .map(|entry_point| ReflectedEntryPoint {
+                name: entry_point.name.clone(),
+                stage: match entry_point.stage {
+                    naga::ShaderStage::Vertex => ShaderStages::VERTEX,
+                    naga::ShaderStage::Fragment => ShaderStages::FRAGMENT,
+                    naga::ShaderStage::Compute => ShaderStages::COMPUTE,
+                },
+                vertex_inputs: match entry_point.stage {
+                    naga::ShaderStage::Vertex => vertex_inputs(&module, &entry_point.function),
+                    _ => vec![],
+                },
+                bindings: vec![],
+            })
+            .collect();
+
+        for (handle, global) in module.global_variables.iter() {
+            let resource = match &global.binding {
+                Some(resource) => resource,
+                None => continue,
+            };
+            // Binding arrays and the like aren't supported by the layouts the engine creates
+            let ty = match binding_type(&module, global) {
+                Some(ty) => ty,
+                None => continue,
+            };
+
+            let mut visibility = ShaderStages::NONE;
+            for (index, entry_point) in entry_points.iter_mut().enumerate() {
+                if info.get_entry_point(index)[handle] != GlobalUse::empty() {
+                    visibility |= entry_point.stage;
+                    entry_point.bindings.push((resource.group, resource.binding));
+                }
+            }
+
+            bindings.push(ReflectedBinding {
+                group: resource.group,
+                binding: resource.binding,
+                name: global.name.clone().unwrap_or_default(),
+                ty,
+                visibility,
+            });
+        }
+        bindings.sort_by_key(|binding| (binding.group, binding.binding));
+
+        Ok(Self { label: label.to_string(), bindings, entry_points })
+    }
+
+    pub fn bindings(&self) -> &[ReflectedBinding] {
+        &self.bindings
+    }
+
+    pub fn entry_points(&self) -> &[ReflectedEntryPoint] {
+        &self.entry_points
+    }
+
+    pub fn entry_point(&self, name: &str) -> HermitResult<&ReflectedEntryPoint> {
+        self.entry_points.iter()
+            .find(|entry_point| entry_point.name == name)
+            .ok_or_else(|| self.mismatch(format!("there is no entry point {}", name)))
+    }
+
+    pub fn binding(&self, group: u32, binding: u32) -> Option<&ReflectedBinding> {
+        self.bindings.iter().find(|reflected| reflected.group == group && reflected.binding == binding)
+    }
+
+    /// One more than the highest group index the shader declares.
+    pub fn group_count(&self) -> u32 {
+        self.bindings.last().map_or(0, |binding| binding.group + 1)
+    }
+
+    pub fn layout_entries(&self, group: u32) -> Vec<BindGroupLayoutEntry> {
+        self.bindings.iter()
+            .filter(|binding| binding.group == group)
+            .map(ReflectedBinding::layout_entry)
+            .collect()
+    }
+
+    /// A layout for every group up to [`ShaderReflection::group_count`], groups the shader skips get empty ones.
+    /// Textures are assumed to be filterable, build the layout by hand for anything else.
+    pub fn bind_group_layouts(&self, canvas: &Canvas, label: Option<&str>) -> Vec<BindGroupLayout> {
+        (0..self.group_count())
+            // Not a BindLayout, shaders are free to skip bindings
+            .map(|group| canvas.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &self.layout_entries(group),
+                label,
+            }))
+            .collect()
+    }
+
+    pub fn vertex_inputs(&self, entry_point: &str) -> HermitResult<&[ReflectedVertexInput]> {
+        Ok(&self.entry_point(entry_point)?.vertex_inputs)
+    }
+
+    /// Layout for a single vertex buffer holding every input of `entry_point` tightly packed.
+    pub fn vertex_layout(&self, entry_point: &str) -> HermitResult<ReflectedVertexLayout> {
+        let mut offset = 0;
+        let attributes = self.vertex_inputs(entry_point)?.iter()
+            .map(|input| {
+                let attribute = VertexAttribute { format: input.format, offset, shader_location: input.location };
+                offset += input.format.size();
+                attribute
+            })
+            .collect();
+
+        Ok(ReflectedVertexLayout { array_stride: offset, attributes })
+    }
+
+    /// Checks that the `buffers` provide every input of `entry_point` in a format its WGSL type can read.
+    pub fn validate_vertex_buffers(&self, entry_point: &str, buffers: &[VertexBufferLayout]) -> HermitResult<()> {
+        let attributes: Vec<&VertexAttribute> = buffers.iter().flat_map(|buffer| buffer.attributes).collect();
+        for (index, attribute) in attributes.iter().enumerate() {
+            if attributes[..index].iter().any(|other| other.shader_location == attribute.shader_location) {
+                return Err(self.mismatch(format!("more than one vertex attribute is bound to @location({})", attribute.shader_location)));
+            }
+        }
+
+        let entry = self.entry_point(entry_point)?;
+        for input in entry.vertex_inputs.iter() {
+            let attribute = attributes.iter().find(|attribute| attribute.shader_location == input.location).ok_or_else(|| {
+                self.mismatch(format!("{} reads {} from @location({}) but none of the {} vertex buffers provide it",
+                    entry.name, input.name, input.location, buffers.len()))
+            })?;
+
+            if !vertex_format_compatible(attribute.format, input.format) {
+                return Err(self.mismatch(format!("{} reads {} from @location({}) as {:?} but the vertex buffer provides {:?}",
+                    entry.name, input.name, input.location, input.format, attribute.format)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that `groups`, the layout entries of the bind groups in order, contain everything `entry_points`
+    /// use with a matching type and visibility. Entries the shader doesn't use are fine.
+    pub fn validate_bind_groups(&self, entry_points: &[&str], groups: &[&[BindGroupLayoutEntry]]) -> HermitResult<()> {
+        for name in entry_points {
+            let entry = self.entry_point(name)?;
+            for &(group, binding) in entry.bindings.iter() {
+                let reflected = self.binding(group, binding).expect("entry points only list reflected bindings");
+                let location = format!("{} uses {} at @group({}) @binding({})", entry.name, reflected.name, group, binding);
+
+                let entries = groups.get(group as usize)
+                    .ok_or_else(|| self.mismatch(format!("{} but the pipeline only has {} bind groups", location, groups.len())))?;
+                let layout = entries.iter()
+                    .find(|layout| layout.binding == binding)
+                    .ok_or_else(|| self.mismatch(format!("{} but bind group {} has no binding {}", location, group, binding)))?;
+
+                if !layout.visibility.contains(entry.stage) {
+                    return Err(self.mismatch(format!("{} but the binding is only visible to {:?}", location, layout.visibility)));
+                }
+                if !binding_type_compatible(&layout.ty, &reflected.ty) {
+                    return Err(self.mismatch(format!("{} as {:?} but the bind group has {:?}", location, reflected.ty, layout.ty)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn mismatch(&self, message: String) -> HermitError {
+        HermitError::ShaderMismatch { shader: self.label.clone(), message }
+    }
+}
+
+/// What naga may accept on a device with `features`, mapped the way wgpu maps them.
+fn capabilities(features: wgpu::Features) -> Capabilities {
+    let mut capabilities = Capabilities::empty();
+    capabilities.set(Capabilities::PUSH_CONSTANT, features.contains(wgpu::Features::PUSH_CONSTANTS));
+    capabilities.set(Capabilities::FLOAT64, features.contains(wgpu::Features::SHADER_FLOAT64));
+    capabilities.set(Capabilities::PRIMITIVE_INDEX, features.contains(wgpu::Features::SHADER_PRIMITIVE_INDEX));
+    capabilities.set(
+        Capabilities::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING,
+        features.contains(wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING),
+    );
+    capabilities.set(
+        Capabilities::UNIFORM_BUFFER_AND_STORAGE_TEXTURE_ARRAY_NON_UNIFORM_INDEXING,
+        features.contains(wgpu::Features::UNIFORM_BUFFER_AND_STORAGE_TEXTURE_ARRAY_NON_UNIFORM_INDEXING),
+    );
+    // wgpu has no feature of its own for this one
+    capabilities.set(
+        Capabilities::SAMPLER_NON_UNIFORM_INDEXING,
+        features.contains(wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING),
+    );
+    capabilities
+}
+
+// Validation errors wrap the actual problem, e.g. a function error caused by an expression error
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+fn vertex_inputs(module: &naga::Module, function: &naga::Function) -> Vec<ReflectedVertexInput> {
+    let mut inputs = vec![];
+    for argument in function.arguments.iter() {
+        let name = argument.name.clone().unwrap_or_default();
+        match &argument.binding {
+            Some(naga::Binding::Location { location, .. }) => inputs.extend(vertex_input(module, *location, name, argument.ty)),
+            Some(naga::Binding::BuiltIn(_)) => {}
+            // Structs group their members' locations
+            None => if let naga::TypeInner::Struct { members, .. } = &module.types[argument.ty].inner {
+                for member in members {
+                    if let Some(naga::Binding::Location { location, .. }) = member.binding {
+                        inputs.extend(vertex_input(module, location, member.name.clone().unwrap_or_default(), member.ty));
+                    }
+                }
+            },
+        }
+    }
+    inputs.sort_by_key(|input| input.location);
+    inputs
+}
+
+fn vertex_input(module: &naga::Module, location: u32, name: String, ty: naga::Handle<naga::Type>) -> Option<ReflectedVertexInput> {
+    use naga::{ScalarKind, VectorSize};
+
+    let (kind, width, size) = match module.types[ty].inner {
+        naga::TypeInner::Scalar { kind, width } => (kind, width, None),
+        naga::TypeInner::Vector { size, kind, width } => (kind, width, Some(size)),
+        _ => return None,
+    };
+
+    let format = match (kind, width, size) {
+        (ScalarKind::Float, 4, None) => VertexFormat::Float32,
+        (ScalarKind::Float, 4, Some(VectorSize::Bi)) => VertexFormat::Float32x2,
+        (ScalarKind::Float, 4, Some(VectorSize::Tri)) => VertexFormat::Float32x3,
+        (ScalarKind::Float, 4, Some(VectorSize::Quad)) => VertexFormat::Float32x4,
+        (ScalarKind::Float, 8, None) => VertexFormat::Float64,
+        (ScalarKind::Float, 8, Some(VectorSize::Bi)) => VertexFormat::Float64x2,
+        (ScalarKind::Float, 8, Some(VectorSize::Tri)) => VertexFormat::Float64x3,
+        (ScalarKind::Float, 8, Some(VectorSize::Quad)) => VertexFormat::Float64x4,
+        (ScalarKind::Uint, 4, None) => VertexFormat::Uint32,
+        (ScalarKind::Uint, 4, Some(VectorSize::Bi)) => VertexFormat::Uint32x2,
+        (ScalarKind::Uint, 4, Some(VectorSize::Tri)) => VertexFormat::Uint32x3,
+        (ScalarKind::Uint, 4, Some(VectorSize::Quad)) => VertexFormat::Uint32x4,
+        (ScalarKind::Sint, 4, None) => VertexFormat::Sint32,
+        (ScalarKind::Sint, 4, Some(VectorSize::Bi)) => VertexFormat::Sint32x2,
+        (ScalarKind::Sint, 4, Some(VectorSize::Tri)) => VertexFormat::Sint32x3,
+        (ScalarKind::Sint, 4, Some(VectorSize::Quad)) => VertexFormat::Sint32x4,
+        _ => return None,
+    };
+
+    Some(ReflectedVertexInput { location, name, format })
+}
+
+fn binding_type(module: &naga::Module, global: &naga::GlobalVariable) -> Option<BindingType> {
+    let inner = &module.types[global.ty].inner;
+    let min_binding_size = || NonZeroU64::new(inner.size(&module.constants) as u64);
+
+    let ty = match global.space {
+        naga::AddressSpace::Uniform => BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: min_binding_size(),
+        },
+        naga::AddressSpace::Storage { access } => BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: !access.contains(naga::StorageAccess::STORE) },
+            has_dynamic_offset: false,
+            min_binding_size: min_binding_size(),
+        },
+        naga::AddressSpace::Handle => match *inner {
+            naga::TypeInner::Sampler { comparison: true } => BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+            naga::TypeInner::Sampler { comparison: false } => BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            naga::TypeInner::Image { dim, arrayed, class } => {
+                let view_dimension = view_dimension(dim, arrayed);
+                match class {
+                    naga::ImageClass::Sampled { kind, multi } => BindingType::Texture {
+                        sample_type: match kind {
+                            naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                            naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                            // Multisampled float textures can't be filtered
+                            _ => wgpu::TextureSampleType::Float { filterable: !multi },
+                        },
+                        view_dimension,
+                        multisampled: multi,
+                    },
+                    naga::ImageClass::Depth { multi } => BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension,
+                        multisampled: multi,
+                    },
+                    naga::ImageClass::Storage { format, access } => BindingType::StorageTexture {
+                        access: if access.contains(naga::StorageAccess::LOAD | naga::StorageAccess::STORE) {
+                            wgpu::StorageTextureAccess::ReadWrite
+                        } else if access.contains(naga::StorageAccess::STORE) {
+                            wgpu::StorageTextureAccess::WriteOnly
+                        } else {
+                            wgpu::StorageTextureAccess::ReadOnly
+                        },
+                        format: storage_format(format),
+                        view_dimension,
+                    },
+                }
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(ty)
+}
+
+fn view_dimension(dim: naga::ImageDimension, arrayed: bool) -> wgpu::TextureViewDimension {
+    match (dim, arrayed) {
+        (naga::ImageDimension::D1, _) => wgpu::TextureViewDimension::D1,
+        (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+        (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+        (naga::ImageDimension::D3, _) => wgpu::TextureViewDimension::D3,
+        (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+        (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+    }
+}
+
+fn storage_format(format: naga::StorageFormat) -> TextureFormat {
+    // Both sides use the same names
+    macro_rules! formats {
+        ($($name:ident),*) => {
+            match format {
+                $(naga::StorageFormat::$name => TextureFormat::$name,)*
+            }
+        };
+    }
+
+    formats!(
+        R8Unorm, R8Snorm, R8Uint, R8Sint, R16Uint, R16Sint, R16Float, Rg8Unorm, Rg8Snorm, Rg8Uint, Rg8Sint,
+        R32Uint, R32Sint, R32Float, Rg16Uint, Rg16Sint, Rg16Float, Rgba8Unorm, Rgba8Snorm, Rgba8Uint, Rgba8Sint,
+        Rgb10a2Unorm, Rg11b10Float, Rg32Uint, Rg32Sint, Rg32Float, Rgba16Uint, Rgba16Sint, Rgba16Float,
+        Rgba32Uint, Rgba32Sint, Rgba32Float
+    )
+}
+
+// Same rules wgpu applies: the scalar kinds have to match and a scalar attribute can't be read as a vector,
+// while a scalar input reads the first component of a vector attribute
+fn vertex_format_compatible(provided: VertexFormat, shader: VertexFormat) -> bool {
+    use VertexFormat::*;
+
+    fn kind(format: VertexFormat) -> naga::ScalarKind {
+        match format {
+            Uint8x2 | Uint8x4 | Uint16x2 | Uint16x4 | Uint32 | Uint32x2 | Uint32x3 | Uint32x4 => naga::ScalarKind::Uint,
+            Sint8x2 | Sint8x4 | Sint16x2 | Sint16x4 | Sint32 | Sint32x2 | Sint32x3 | Sint32x4 => naga::ScalarKind::Sint,
+            // Normalized and half formats are read as floats
+            _ => naga::ScalarKind::Float,
+        }
+    }
+    let is_scalar = |format| matches!(format, Float32 | Uint32 | Sint32 | Float64);
+
+    kind(provided) == kind(shader) && (is_scalar(shader) || !is_scalar(provided))
+}
+
+fn binding_type_compatible(layout: &BindingType, shader: &BindingType) -> bool {
+    use wgpu::TextureSampleType as Sample;
+
+    match (layout, shader) {
+        (BindingType::Buffer { ty: layout_ty, min_binding_size: layout_size, .. }, BindingType::Buffer { ty: shader_ty, min_binding_size: shader_size, .. }) => {
+            let kind = match (layout_ty, shader_ty) {
+                (wgpu::BufferBindingType::Uniform, wgpu::BufferBindingType::Uniform) => true,
+                // Read-write buffers can be bound to read-only declarations, not the other way around
+                (wgpu::BufferBindingType::Storage { read_only: layout_read_only }, wgpu::BufferBindingType::Storage { read_only }) => !layout_read_only || *read_only,
+                _ => false,
+            };
+            let size = match (layout_size, shader_size) {
+                (Some(layout_size), Some(shader_size)) => layout_size >= shader_size,
+                _ => true,
+            };
+            kind && size
+        }
+        (BindingType::Sampler(layout), BindingType::Sampler(shader)) => {
+            (*layout == wgpu::SamplerBindingType::Comparison) == (*shader == wgpu::SamplerBindingType::Comparison)
+        }
+        (BindingType::Texture { sample_type: layout_sample, view_dimension: layout_dimension, multisampled: layout_multi },
+            BindingType::Texture { sample_type: shader_sample, view_dimension: shader_dimension, multisampled: shader_multi }) => {
+            let sample = matches!((layout_sample, shader_sample),
+                (Sample::Float { .. }, Sample::Float { .. })
+                | (Sample::Depth, Sample::Depth)
+                | (Sample::Depth, Sample::Float { .. })
+                | (Sample::Sint, Sample::Sint)
+                | (Sample::Uint, Sample::Uint));
+            sample && layout_dimension == shader_dimension && layout_multi == shader_multi
+        }
+        (BindingType::StorageTexture { .. }, BindingType::StorageTexture { .. }) => layout == shader,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu::{BufferBindingType, SamplerBindingType, TextureSampleType, TextureViewDimension};
+
+    fn buffer(ty: BufferBindingType, size: Option<u64>) -> BindingType {
+        BindingType::Buffer { ty, has_dynamic_offset: false, min_binding_size: size.and_then(NonZeroU64::new) }
+    }
+
+    fn texture(sample_type: TextureSampleType, view_dimension: TextureViewDimension, multisampled: bool) -> BindingType {
+        BindingType::Texture { sample_type, view_dimension, multisampled }
+    }
+
+    #[test]
+    fn scalar_inputs_read_vector_attributes() {
+        assert!(vertex_format_compatible(VertexFormat::Float32x4, VertexFormat::Float32));
+        assert!(vertex_format_compatible(VertexFormat::Uint32x2, VertexFormat::Uint32));
+        assert!(vertex_format_compatible(VertexFormat::Float32, VertexFormat::Float32));
+    }
+
+    #[test]
+    fn vector_inputs_dont_read_scalar_attributes() {
+        assert!(!vertex_format_compatible(VertexFormat::Float32, VertexFormat::Float32x3));
+        assert!(!vertex_format_compatible(VertexFormat::Sint32, VertexFormat::Sint32x2));
+    }
+
+    #[test]
+    fn vectors_of_any_size_match() {
+        assert!(vertex_format_compatible(VertexFormat::Float32x2, VertexFormat::Float32x4));
+        assert!(vertex_format_compatible(VertexFormat::Float32x4, VertexFormat::Float32x3));
+    }
+
+    #[test]
+    fn vertex_kinds_have_to_match() {
+        assert!(vertex_format_compatible(VertexFormat::Unorm8x4, VertexFormat::Float32x4));
+        assert!(vertex_format_compatible(VertexFormat::Float16x2, VertexFormat::Float32x2));
+        assert!(!vertex_format_compatible(VertexFormat::Uint32x4, VertexFormat::Float32x4));
+        assert!(!vertex_format_compatible(VertexFormat::Sint32, VertexFormat::Uint32));
+    }
+
+    #[test]
+    fn buffer_bindings() {
+        let uniform = buffer(BufferBindingType::Uniform, None);
+        let read_only = buffer(BufferBindingType::Storage { read_only: true }, None);
+        let read_write = buffer(BufferBindingType::Storage { read_only: false }, None);
+
+        assert!(binding_type_compatible(&uniform, &uniform));
+        assert!(!binding_type_compatible(&uniform, &read_only));
+        assert!(binding_type_compatible(&read_write, &read_only));
+        assert!(!binding_type_compatible(&read_only, &read_write));
+    }
+
+    #[test]
+    fn buffer_bindings_have_to_be_big_enough() {
+        let shader = buffer(BufferBindingType::Uniform, Some(64));
+
+        assert!(binding_type_compatible(&buffer(BufferBindingType::Uniform, Some(64)), &shader));
+        assert!(binding_type_compatible(&buffer(BufferBindingType::Uniform, Some(128)), &shader));
+        assert!(!binding_type_compatible(&buffer(BufferBindingType::Uniform, Some(16)), &shader));
+        assert!(binding_type_compatible(&buffer(BufferBindingType::Uniform, None), &shader));
+    }
+
+    #[test]
+    fn sampler_bindings() {
+        let filtering = BindingType::Sampler(SamplerBindingType::Filtering);
+        let non_filtering = BindingType::Sampler(SamplerBindingType::NonFiltering);
+        let comparison = BindingType::Sampler(SamplerBindingType::Comparison);
+
+        assert!(binding_type_compatible(&non_filtering, &filtering));
+        assert!(binding_type_compatible(&comparison, &comparison));
+        assert!(!binding_type_compatible(&filtering, &comparison));
+        assert!(!binding_type_compatible(&comparison, &filtering));
+    }
+
+    #[test]
+    fn texture_bindings() {
+        let float = texture(TextureSampleType::Float { filterable: true }, TextureViewDimension::D2, false);
+
+        assert!(binding_type_compatible(&texture(TextureSampleType::Float { filterable: false }, TextureViewDimension::D2, false), &float));
+        assert!(binding_type_compatible(&texture(TextureSampleType::Depth, TextureViewDimension::D2, false), &float));
+        assert!(!binding_type_compatible(&float, &texture(TextureSampleType::Depth, TextureViewDimension::D2, false)));
+        assert!(!binding_type_compatible(&texture(TextureSampleType::Uint, TextureViewDimension::D2, false), &float));
+        assert!(!binding_type_compatible(&texture(TextureSampleType::Float { filterable: true }, TextureViewDimension::Cube, false), &float));
+        assert!(!binding_type_compatible(&texture(TextureSampleType::Float { filterable: true }, TextureViewDimension::D2, true), &float));
+    }
+
+    #[test]
+    fn storage_texture_bindings_match_exactly() {
+        let storage = |format, access| BindingType::StorageTexture { access, format, view_dimension: TextureViewDimension::D2 };
+        let write = storage(TextureFormat::Rgba8Unorm, wgpu::StorageTextureAccess::WriteOnly);
+
+        assert!(binding_type_compatible(&write, &write));
+        assert!(!binding_type_compatible(&storage(TextureFormat::Rgba16Float, wgpu::StorageTextureAccess::WriteOnly), &write));
+        assert!(!binding_type_compatible(&storage(TextureFormat::Rgba8Unorm, wgpu::StorageTextureAccess::ReadWrite), &write));
+    }
+
+    #[test]
+    fn binding_kinds_have_to_match() {
+        assert!(!binding_type_compatible(&BindingType::Sampler(SamplerBindingType::Filtering), &buffer(BufferBindingType::Uniform, None)));
+        assert!(!binding_type_compatible(&buffer(BufferBindingType::Uniform, None), &texture(TextureSampleType::Float { filterable: true }, TextureViewDimension::D2, false)));
+    }
+}
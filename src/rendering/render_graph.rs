@@ -0,0 +1,605 @@
+use std::collections::HashMap;
+use std::iter;
+use wgpu::{Buffer, CommandEncoder, RenderPass, TextureView};
+use crate::error::{HermitError, HermitResult};
+use crate::rendering::canvas::{Canvas, CanvasFrame};
+use crate::rendering::profiler::Profiler;
+use crate::util::textures::Texture;
+
+/// Handle to a texture or buffer managed by a [`RenderGraph`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ResourceId(usize);
+
+impl ResourceId {
+    /// The texture of the frame that is presented at the end.
+    pub const SURFACE: ResourceId = ResourceId(0);
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TextureSize {
+    // Same size as the canvas
+    Canvas,
+    // Canvas size divided by the given factor, e.g. for downsampling chains
+    Fraction(u32),
+    Fixed(u32, u32),
+}
+
+impl TextureSize {
+    fn resolve(&self, canvas: &Canvas) -> (u32, u32) {
+        match *self {
+            TextureSize::Canvas => (canvas.config.width, canvas.config.height),
+            TextureSize::Fraction(divisor) => (
+                (canvas.config.width / divisor).max(1),
+                (canvas.config.height / divisor).max(1),
+            ),
+            TextureSize::Fixed(width, height) => (width, height),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureDesc {
+    pub format: wgpu::TextureFormat,
+    pub size: TextureSize,
+    pub usage: wgpu::TextureUsages,
+}
+
+impl TextureDesc {
+    /// Canvas sized texture that can be rendered to and sampled afterwards.
+    pub fn new(format: wgpu::TextureFormat) -> Self {
+        Self {
+            format,
+            size: TextureSize::Canvas,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        }
+    }
+
+    pub fn with_size(mut self, size: TextureSize) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn with_usage(mut self, usage: wgpu::TextureUsages) -> Self {
+        self.usage = usage;
+        self
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BufferDesc {
+    pub size: wgpu::BufferAddress,
+    pub usage: wgpu::BufferUsages,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum ResourceDesc {
+    Surface,
+    // Owned outside of the graph, only used to order the passes
+    External,
+    Texture(TextureDesc),
+    Buffer(BufferDesc),
+}
+
+enum PhysicalResource {
+    Texture(Texture),
+    Buffer(Buffer),
+}
+
+/// A single step of the graph, e.g. drawing the scene or applying a post effect.
+pub trait RenderNode<T> {
+    fn execute(&mut self, context: &mut NodeContext, scene: &T);
+}
+
+struct FnNode<F>(F);
+
+impl<T, F> RenderNode<T> for FnNode<F> where F: FnMut(&mut NodeContext, &T) {
+    fn execute(&mut self, context: &mut NodeContext, scene: &T) {
+        (self.0)(context, scene)
+    }
+}
+
+struct PassEntry<T> {
+    name: String,
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+    node: Box<dyn RenderNode<T>>,
+}
+
+/// Orders passes by the resources they read and write, allocates the transient
+/// resources they need and records everything into one command encoder per frame.
+pub struct RenderGraph<T> {
+    resources: Vec<(String, ResourceDesc)>,
+    passes: Vec<PassEntry<T>>,
+    // Passes in execution order, culled passes are left out
+    order: Vec<usize>,
+    // Maps every transient resource to the physical resource backing it
+    assignment: HashMap<ResourceId, usize>,
+    slots: Vec<ResourceDesc>,
+    physical: Vec<PhysicalResource>,
+    compiled: bool,
+    allocated_size: (u32, u32),
+}
+
+impl<T> Default for RenderGraph<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> RenderGraph<T> {
+    pub fn new() -> Self {
+        Self {
+            resources: vec![("surface".to_string(), ResourceDesc::Surface)],
+            passes: vec![],
+            order: vec![],
+            assignment: HashMap::new(),
+            slots: vec![],
+            physical: vec![],
+            compiled: false,
+            allocated_size: (0, 0),
+        }
+    }
+
+    pub fn create_texture(&mut self, name: &str, desc: TextureDesc) -> ResourceId {
+        self.add_resource(name, ResourceDesc::Texture(desc))
+    }
+
+    pub fn create_buffer(&mut self, name: &str, desc: BufferDesc) -> ResourceId {
+        self.add_resource(name, ResourceDesc::Buffer(desc))
+    }
+
+    /// A resource owned outside of the graph, e.g. the scene target of a
+    /// [`crate::rendering::post_process::PostStack`]. Passes using it are ordered like for any other resource,
+    /// but the graph never allocates it, so nodes have to get to it themselves.
+    pub fn import(&mut self, name: &str) -> ResourceId {
+        self.add_resource(name, ResourceDesc::External)
+    }
+
+    fn add_resource(&mut self, name: &str, desc: ResourceDesc) -> ResourceId {
+        self.compiled = false;
+        self.resources.push((name.to_string(), desc));
+        ResourceId(self.resources.len() - 1)
+    }
+
+    /// Adds a pass. Passes sharing a resource run in the order they were added,
+    /// a read sees what the passes added before it wrote.
+    pub fn add_pass(&mut self, name: &str, reads: &[ResourceId], writes: &[ResourceId], node: impl RenderNode<T> + 'static) {
+        self.compiled = false;
+        self.passes.push(PassEntry {
+            name: name.to_string(),
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            node: Box::new(node),
+        });
+    }
+
+    pub fn add_pass_fn<F>(&mut self, name: &str, reads: &[ResourceId], writes: &[ResourceId], node: F)
+        where F: FnMut(&mut NodeContext, &T) + 'static {
+        self.add_pass(name, reads, writes, FnNode(node));
+    }
+
+    /// Names of the passes that will run, in execution order.
+    pub fn pass_order(&self) -> Vec<&str> {
+        self.order.iter().map(|index| self.passes[*index].name.as_str()).collect()
+    }
+
+    /// Orders the passes and works out which transient resources can share memory.
+    /// Called automatically by [`RenderGraph::execute`] after the graph changed.
+    pub fn compile(&mut self) -> HermitResult<()> {
+        for pass in &self.passes {
+            if let Some(resource) = pass.reads.iter().chain(pass.writes.iter()).find(|id| id.0 >= self.resources.len()) {
+                return Err(HermitError::RenderGraphInvalid(format!("Pass {} uses unknown resource {:?}", pass.name, resource)));
+            }
+        }
+
+        let dependencies = self.dependencies();
+        let live = self.live_passes(&dependencies);
+        self.order = self.sort(&dependencies, &live)?;
+        self.assign_resources()?;
+
+        self.physical.clear();
+        self.compiled = true;
+        Ok(())
+    }
+
+    // For every pass the passes that have to run before it. Every write starts a new version of the resource:
+    // readers wait for the last writer added before them, writers for the previous writer and its readers
+    fn dependencies(&self) -> Vec<Vec<usize>> {
+        let mut dependencies = vec![vec![]; self.passes.len()];
+        let mut last_writer: Vec<Option<usize>> = vec![None; self.resources.len()];
+        let mut readers: Vec<Vec<usize>> = vec![vec![]; self.resources.len()];
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            // A pass reading and writing a resource reads the version before its own write
+            for id in pass.reads.iter() {
+                dependencies[index].extend(last_writer[id.0]);
+                readers[id.0].push(index);
+            }
+            for id in pass.writes.iter() {
+                dependencies[index].extend(last_writer[id.0]);
+                dependencies[index].extend(readers[id.0].drain(..).filter(|reader| *reader != index));
+                last_writer[id.0] = Some(index);
+            }
+        }
+
+        for (index, dependencies) in dependencies.iter_mut().enumerate() {
+            dependencies.retain(|dependency| *dependency != index);
+            dependencies.sort_unstable();
+            dependencies.dedup();
+        }
+        dependencies
+    }
+
+    // Passes that end up contributing to the surface, everything else is culled
+    fn live_passes(&self, dependencies: &[Vec<usize>]) -> Vec<bool> {
+        let mut live = vec![false; self.passes.len()];
+        let mut stack: Vec<usize> = (0..self.passes.len())
+            .filter(|pass| self.passes[*pass].writes.contains(&ResourceId::SURFACE))
+            .collect();
+
+        while let Some(pass) = stack.pop() {
+            if live[pass] { continue; }
+            live[pass] = true;
+            stack.extend(dependencies[pass].iter());
+        }
+
+        for (pass, is_live) in live.iter().enumerate() {
+            if !is_live {
+                log::debug!("Culling render pass {} because nothing uses its output", self.passes[pass].name);
+            }
+        }
+
+        live
+    }
+
+    fn sort(&self, dependencies: &[Vec<usize>], live: &[bool]) -> HermitResult<Vec<usize>> {
+        let mut order = vec![];
+        let mut done = vec![false; self.passes.len()];
+
+        // Repeatedly take the first pass whose dependencies ran, this keeps the added order where possible
+        while order.len() < live.iter().filter(|is_live| **is_live).count() {
+            let next = (0..self.passes.len()).find(|pass| {
+                live[*pass] && !done[*pass] && dependencies[*pass].iter().all(|dependency| done[*dependency])
+            });
+
+            match next {
+                Some(pass) => {
+                    done[pass] = true;
+                    order.push(pass);
+                }
+                None => {
+                    let stuck: Vec<&str> = (0..self.passes.len())
+                        .filter(|pass| live[*pass] && !done[*pass])
+                        .map(|pass| self.passes[pass].name.as_str())
+                        .collect();
+                    return Err(HermitError::RenderGraphInvalid(format!("Cycle between passes {:?}", stuck)));
+                }
+            }
+        }
+
+        Ok(order)
+    }
+
+    fn assign_resources(&mut self) -> HermitResult<()> {
+        // First and last position in the execution order every resource is used at
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.resources.len()];
+        for (position, pass) in self.order.iter().enumerate() {
+            let pass = &self.passes[*pass];
+            for id in pass.reads.iter().chain(pass.writes.iter()) {
+                let lifetime = lifetimes[id.0].get_or_insert((position, position));
+                lifetime.1 = position;
+            }
+        }
+
+        for (resource, lifetime) in lifetimes.iter().enumerate() {
+            if let (Some((first, _)), ResourceDesc::Texture(_) | ResourceDesc::Buffer(_)) = (lifetime, self.resources[resource].1) {
+                let pass = &self.passes[self.order[*first]];
+                if !pass.writes.contains(&ResourceId(resource)) {
+                    return Err(HermitError::RenderGraphInvalid(format!("Pass {} reads {} before anything writes it", pass.name, self.resources[resource].0)));
+                }
+            }
+        }
+
+        // Resources whose lifetimes don't overlap alias the same physical resource
+        self.assignment.clear();
+        let mut slots: Vec<(ResourceDesc, usize)> = vec![];
+        let mut transient: Vec<(usize, (usize, usize))> = lifetimes.iter().enumerate()
+            .filter(|(resource, _)| !matches!(self.resources[*resource].1, ResourceDesc::Surface | ResourceDesc::External))
+            .filter_map(|(resource, lifetime)| lifetime.map(|lifetime| (resource, lifetime)))
+            .collect();
+        transient.sort_by_key(|(_, (first, _))| *first);
+
+        for (resource, (first, last)) in transient {
+            let desc = self.resources[resource].1;
+            let slot = match slots.iter().position(|(slot_desc, free_after)| *slot_desc == desc && *free_after < first) {
+                Some(slot) => slot,
+                None => {
+                    slots.push((desc, 0));
+                    slots.len() - 1
+                }
+            };
+            slots[slot].1 = last;
+            self.assignment.insert(ResourceId(resource), slot);
+        }
+
+        self.slots = slots.into_iter().map(|(desc, _)| desc).collect();
+
+        Ok(())
+    }
+
+    fn allocate(&mut self, canvas: &Canvas) {
+        let size = (canvas.config.width, canvas.config.height);
+        if !self.physical.is_empty() && self.allocated_size == size {
+            return;
+        }
+
+        self.physical = self.slots.iter().map(|desc| match desc {
+            ResourceDesc::Texture(desc) => {
+                let (width, height) = desc.size.resolve(canvas);
+                PhysicalResource::Texture(Texture::create_color_texture(&canvas.device, desc.format, width, height, desc.usage, "Render Graph Texture"))
+            }
+            ResourceDesc::Buffer(desc) => PhysicalResource::Buffer(canvas.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Render Graph Buffer"),
+                size: desc.size,
+                usage: desc.usage,
+                mapped_at_creation: false,
+            })),
+            ResourceDesc::Surface | ResourceDesc::External => unreachable!(),
+        }).collect();
+        self.allocated_size = size;
+    }
+
+    /// Records every pass into a single encoder and submits it.
+    pub fn execute(&mut self, canvas: &Canvas, frame: &CanvasFrame, scene: &T) -> HermitResult<()> {
+        self.record(canvas, frame, scene, None)
+    }
+
+    /// Like [`RenderGraph::execute`] but times every pass on the gpu, in a scope named after the pass.
+    pub fn execute_profiled(&mut self, canvas: &Canvas, frame: &CanvasFrame, scene: &T, profiler: &mut Profiler) -> HermitResult<()> {
+        self.record(canvas, frame, scene, Some(profiler))
+    }
+
+    fn record(&mut self, canvas: &Canvas, frame: &CanvasFrame, scene: &T, mut profiler: Option<&mut Profiler>) -> HermitResult<()> {
+        if !self.compiled {
+            self.compile()?;
+        }
+        self.allocate(canvas);
+
+        let mut encoder = canvas.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Render Graph Encoder"),
+        });
+
+        for pass in self.order.iter() {
+            let entry = &mut self.passes[*pass];
+            if let Some(profiler) = profiler.as_deref_mut() {
+                profiler.begin_gpu_scope(&mut encoder, &entry.name);
+            }
+
+            let mut context = NodeContext {
+                canvas,
+                frame,
+                encoder: &mut encoder,
+                assignment: &self.assignment,
+                physical: &self.physical,
+            };
+            entry.node.execute(&mut context, scene);
+
+            if let Some(profiler) = profiler.as_deref_mut() {
+                profiler.end_gpu_scope(&mut encoder);
+            }
+        }
+
+        if let Some(profiler) = profiler.as_deref_mut() {
+            profiler.resolve(&mut encoder);
+        }
+        canvas.uploader.finish();
+        canvas.queue.submit(iter::once(encoder.finish()));
+        canvas.uploader.recall();
+        if let Some(profiler) = profiler {
+            profiler.end_frame();
+        }
+        Ok(())
+    }
+}
+
+/// What a node gets to record its work with.
+pub struct NodeContext<'a> {
+    pub canvas: &'a Canvas,
+    pub frame: &'a CanvasFrame,
+    pub encoder: &'a mut CommandEncoder,
+    assignment: &'a HashMap<ResourceId, usize>,
+    physical: &'a [PhysicalResource],
+}
+
+impl<'a> NodeContext<'a> {
+    fn physical(&self, id: ResourceId) -> &'a PhysicalResource {
+        let slot = self.assignment.get(&id).expect("Resource is not used by any pass that runs");
+        &self.physical[*slot]
+    }
+
+    /// The texture backing a graph resource, not available for [`ResourceId::SURFACE`] and imported resources.
+    pub fn texture(&self, id: ResourceId) -> &'a Texture {
+        match self.physical(id) {
+            PhysicalResource::Texture(texture) => texture,
+            PhysicalResource::Buffer(_) => panic!("Resource {:?} is a buffer", id),
+        }
+    }
+
+    pub fn view(&self, id: ResourceId) -> &'a TextureView {
+        if id == ResourceId::SURFACE {
+            &self.frame.view
+        } else {
+            &self.texture(id).view
+        }
+    }
+
+    pub fn buffer(&self, id: ResourceId) -> &'a Buffer {
+        match self.physical(id) {
+            PhysicalResource::Buffer(buffer) => buffer,
+            PhysicalResource::Texture(_) => panic!("Resource {:?} is a texture", id),
+        }
+    }
+
+    /// Begins a pass rendering into `targets`, the surface goes through the canvas msaa target
+    /// and gets the canvas depth buffer attached.
+    pub fn begin_render_pass(&mut self, label: &str, targets: &[(ResourceId, wgpu::LoadOp<wgpu::Color>)]) -> RenderPass<'_> {
+        let canvas = self.canvas;
+        let frame = self.frame;
+        let color_attachments: Vec<Option<wgpu::RenderPassColorAttachment>> = targets.iter().map(|(id, load)| {
+            if *id == ResourceId::SURFACE {
+                Some(canvas.color_attachment(frame, *load))
+            } else {
+                Some(wgpu::RenderPassColorAttachment {
+                    view: self.view(*id),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: *load,
+                        store: true,
+                    },
+                })
+            }
+        }).collect();
+
+        let depth_stencil_attachment = if targets.iter().any(|(id, _)| *id == ResourceId::SURFACE) {
+            canvas.depth_stencil_attachment()
+        } else {
+            None
+        };
+
+        self.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &color_attachments,
+            depth_stencil_attachment,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texture(graph: &mut RenderGraph<()>, name: &str) -> ResourceId {
+        graph.create_texture(name, TextureDesc::new(wgpu::TextureFormat::Rgba8Unorm))
+    }
+
+    fn pass(graph: &mut RenderGraph<()>, name: &str, reads: &[ResourceId], writes: &[ResourceId]) {
+        graph.add_pass_fn(name, reads, writes, |_, _| {});
+    }
+
+    #[test]
+    fn ping_pong_is_not_a_cycle() {
+        let mut graph = RenderGraph::new();
+        let a = texture(&mut graph, "a");
+        let b = texture(&mut graph, "b");
+        pass(&mut graph, "scene", &[], &[a]);
+        pass(&mut graph, "blur horizontal", &[a], &[b]);
+        pass(&mut graph, "blur vertical", &[b], &[a]);
+        pass(&mut graph, "composite", &[a], &[ResourceId::SURFACE]);
+
+        graph.compile().unwrap();
+        assert_eq!(graph.pass_order(), ["scene", "blur horizontal", "blur vertical", "composite"]);
+    }
+
+    #[test]
+    fn overwrite_waits_for_readers_of_the_previous_version() {
+        let mut graph = RenderGraph::new();
+        let a = texture(&mut graph, "a");
+        pass(&mut graph, "scene", &[], &[a]);
+        pass(&mut graph, "copy", &[a], &[ResourceId::SURFACE]);
+        pass(&mut graph, "overwrite", &[], &[a]);
+        pass(&mut graph, "overlay", &[a], &[ResourceId::SURFACE]);
+
+        graph.compile().unwrap();
+        assert_eq!(graph.dependencies()[2], [0, 1]);
+        assert_eq!(graph.dependencies()[3], [1, 2]);
+        assert_eq!(graph.pass_order(), ["scene", "copy", "overwrite", "overlay"]);
+    }
+
+    #[test]
+    fn reading_and_writing_the_same_resource_sees_the_previous_version() {
+        let mut graph = RenderGraph::new();
+        let a = texture(&mut graph, "a");
+        pass(&mut graph, "scene", &[], &[a]);
+        pass(&mut graph, "in place", &[a], &[a]);
+        pass(&mut graph, "composite", &[a], &[ResourceId::SURFACE]);
+
+        graph.compile().unwrap();
+        assert_eq!(graph.dependencies(), [vec![], vec![0], vec![1]]);
+    }
+
+    #[test]
+    fn read_before_any_write_is_an_error() {
+        let mut graph = RenderGraph::new();
+        let a = texture(&mut graph, "a");
+        pass(&mut graph, "composite", &[a], &[ResourceId::SURFACE]);
+        pass(&mut graph, "scene", &[], &[a]);
+
+        assert!(graph.compile().is_err());
+    }
+
+    #[test]
+    fn keeps_the_added_order_of_independent_passes() {
+        let mut graph = RenderGraph::new();
+        let a = texture(&mut graph, "a");
+        let b = texture(&mut graph, "b");
+        pass(&mut graph, "shadows", &[], &[b]);
+        pass(&mut graph, "scene", &[], &[a]);
+        pass(&mut graph, "composite", &[a, b], &[ResourceId::SURFACE]);
+
+        graph.compile().unwrap();
+        assert_eq!(graph.pass_order(), ["shadows", "scene", "composite"]);
+    }
+
+    #[test]
+    fn culls_passes_that_dont_reach_the_surface() {
+        let mut graph = RenderGraph::new();
+        let a = texture(&mut graph, "a");
+        let unused = texture(&mut graph, "unused");
+        pass(&mut graph, "debug", &[], &[unused]);
+        pass(&mut graph, "scene", &[], &[a]);
+        pass(&mut graph, "debug view", &[unused], &[]);
+        pass(&mut graph, "composite", &[a], &[ResourceId::SURFACE]);
+
+        graph.compile().unwrap();
+        assert_eq!(graph.pass_order(), ["scene", "composite"]);
+    }
+
+    #[test]
+    fn aliases_resources_whose_lifetimes_dont_overlap() {
+        let mut graph = RenderGraph::new();
+        let a = texture(&mut graph, "a");
+        let b = texture(&mut graph, "b");
+        let c = texture(&mut graph, "c");
+        pass(&mut graph, "scene", &[], &[a]);
+        pass(&mut graph, "first", &[a], &[b]);
+        pass(&mut graph, "second", &[b], &[c]);
+        pass(&mut graph, "composite", &[c], &[ResourceId::SURFACE]);
+
+        graph.compile().unwrap();
+        assert_eq!(graph.assignment[&a], graph.assignment[&c]);
+        assert_ne!(graph.assignment[&a], graph.assignment[&b]);
+        assert_eq!(graph.slots.len(), 2);
+    }
+
+    #[test]
+    fn orders_but_never_allocates_imported_resources() {
+        let mut graph = RenderGraph::new();
+        let scene = graph.import("scene");
+        pass(&mut graph, "post", &[scene], &[ResourceId::SURFACE]);
+        pass(&mut graph, "scene", &[], &[scene]);
+        pass(&mut graph, "overlay", &[scene], &[ResourceId::SURFACE]);
+
+        graph.compile().unwrap();
+        assert_eq!(graph.pass_order(), ["post", "scene", "overlay"]);
+        assert!(graph.assignment.is_empty());
+        assert!(graph.slots.is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_resources() {
+        let mut graph = RenderGraph::new();
+        pass(&mut graph, "scene", &[ResourceId(7)], &[ResourceId::SURFACE]);
+
+        assert!(graph.compile().is_err());
+    }
+}
@@ -0,0 +1,239 @@
+use std::ops::Range;
+use std::sync::Mutex;
+use wgpu::{BindGroup, Buffer, BufferAddress, BufferSlice, Color, DynamicOffset, IndexFormat, QuerySet, RenderBundle, RenderPass, RenderPipeline, ShaderStages};
+
+/// Work recorded over one frame.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    pub draw_calls: u64,
+    /// Vertices processed by non indexed draws, per instance.
+    pub vertices: u64,
+    /// Indices processed by indexed draws, per instance.
+    pub indices: u64,
+    pub instances: u64,
+    /// Assumes triangle lists, draws with other topologies still count as a third of their vertices.
+    pub triangles: u64,
+    pub pipeline_switches: u64,
+    pub bind_group_switches: u64,
+    /// Bytes written into buffers through [`crate::rendering::canvas::Canvas::write_buffer`],
+    /// [`crate::rendering::canvas::Canvas::stage_buffer`] and [`crate::rendering::buffer::BufferBuilder`].
+    pub uploaded_bytes: u64,
+}
+
+impl RenderStats {
+    pub fn add(&mut self, other: &RenderStats) {
+        self.draw_calls += other.draw_calls;
+        self.vertices += other.vertices;
+        self.indices += other.indices;
+        self.instances += other.instances;
+        self.triangles += other.triangles;
+        self.pipeline_switches += other.pipeline_switches;
+        self.bind_group_switches += other.bind_group_switches;
+        self.uploaded_bytes += other.uploaded_bytes;
+    }
+}
+
+/// Counters of the canvas, passes add to the current frame when they end.
+#[derive(Debug, Default)]
+pub struct FrameStats {
+    current: Mutex<RenderStats>,
+    last: Mutex<RenderStats>,
+}
+
+impl FrameStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, stats: &RenderStats) {
+        self.current.lock().unwrap().add(stats);
+    }
+
+    pub fn record_upload(&self, bytes: u64) {
+        self.current.lock().unwrap().uploaded_bytes += bytes;
+    }
+
+    /// What has been recorded since the last [`FrameStats::end_frame`].
+    pub fn current(&self) -> RenderStats {
+        *self.current.lock().unwrap()
+    }
+
+    /// Stats of the last finished frame.
+    pub fn last_frame(&self) -> RenderStats {
+        *self.last.lock().unwrap()
+    }
+
+    /// Finishes the frame and starts counting the next one from zero, returns the finished frame.
+    pub fn end_frame(&self) -> RenderStats {
+        let finished = std::mem::take(&mut *self.current.lock().unwrap());
+        *self.last.lock().unwrap() = finished;
+        finished
+    }
+}
+
+/// Render pass counting the work recorded into it, redundant pipeline and bind group changes are skipped.
+pub struct StatsRenderPass<'a> {
+    render_pass: RenderPass<'a>,
+    frame_stats: &'a FrameStats,
+    stats: RenderStats,
+    pipeline: Option<&'a RenderPipeline>,
+    bind_groups: Vec<Option<(&'a BindGroup, Vec<DynamicOffset>)>>,
+}
+
+impl<'a> StatsRenderPass<'a> {
+    pub fn new(render_pass: RenderPass<'a>, frame_stats: &'a FrameStats) -> Self {
+        Self {
+            render_pass,
+            frame_stats,
+            stats: RenderStats::default(),
+            pipeline: None,
+            bind_groups: vec![],
+        }
+    }
+
+    /// Counted so far in this pass, it's added to the frame once the pass is dropped.
+    pub fn stats(&self) -> &RenderStats {
+        &self.stats
+    }
+
+    pub fn set_pipeline(&mut self, pipeline: &'a RenderPipeline) {
+        if self.pipeline.is_some_and(|current| std::ptr::eq(current, pipeline)) { return; }
+
+        self.render_pass.set_pipeline(pipeline);
+        self.pipeline = Some(pipeline);
+        self.stats.pipeline_switches += 1;
+    }
+
+    pub fn set_bind_group(&mut self, index: u32, bind_group: &'a BindGroup, offsets: &[DynamicOffset]) {
+        let slot = index as usize;
+        if self.bind_groups.len() <= slot {
+            self.bind_groups.resize(slot + 1, None);
+        }
+        if let Some((current, current_offsets)) = &self.bind_groups[slot] {
+            if std::ptr::eq(*current, bind_group) && current_offsets.as_slice() == offsets { return; }
+        }
+
+        self.render_pass.set_bind_group(index, bind_group, offsets);
+        self.bind_groups[slot] = Some((bind_group, offsets.to_vec()));
+        self.stats.bind_group_switches += 1;
+    }
+
+    pub fn set_vertex_buffer(&mut self, slot: u32, buffer_slice: BufferSlice<'a>) {
+        self.render_pass.set_vertex_buffer(slot, buffer_slice);
+    }
+
+    pub fn set_index_buffer(&mut self, buffer_slice: BufferSlice<'a>, index_format: IndexFormat) {
+        self.render_pass.set_index_buffer(buffer_slice, index_format);
+    }
+
+    pub fn draw(&mut self, vertices: Range<u32>, instances: Range<u32>) {
+        let instance_count = instances.len() as u64;
+        let vertex_count = vertices.len() as u64 * instance_count;
+        self.render_pass.draw(vertices, instances);
+
+        self.stats.draw_calls += 1;
+        self.stats.vertices += vertex_count;
+        self.stats.instances += instance_count;
+        self.stats.triangles += vertex_count / 3;
+    }
+
+    pub fn draw_indexed(&mut self, indices: Range<u32>, base_vertex: i32, instances: Range<u32>) {
+        let instance_count = instances.len() as u64;
+        let index_count = indices.len() as u64 * instance_count;
+        self.render_pass.draw_indexed(indices, base_vertex, instances);
+
+        self.stats.draw_calls += 1;
+        self.stats.indices += index_count;
+        self.stats.instances += instance_count;
+        self.stats.triangles += index_count / 3;
+    }
+
+    /// Counts as a draw call, the vertex and instance counts live on the gpu.
+    pub fn draw_indirect(&mut self, indirect_buffer: &'a Buffer, indirect_offset: BufferAddress) {
+        self.render_pass.draw_indirect(indirect_buffer, indirect_offset);
+        self.stats.draw_calls += 1;
+    }
+
+    pub fn draw_indexed_indirect(&mut self, indirect_buffer: &'a Buffer, indirect_offset: BufferAddress) {
+        self.render_pass.draw_indexed_indirect(indirect_buffer, indirect_offset);
+        self.stats.draw_calls += 1;
+    }
+
+    /// Counts `count` draw calls.
+    pub fn multi_draw_indirect(&mut self, indirect_buffer: &'a Buffer, indirect_offset: BufferAddress, count: u32) {
+        self.render_pass.multi_draw_indirect(indirect_buffer, indirect_offset, count);
+        self.stats.draw_calls += count as u64;
+    }
+
+    pub fn multi_draw_indexed_indirect(&mut self, indirect_buffer: &'a Buffer, indirect_offset: BufferAddress, count: u32) {
+        self.render_pass.multi_draw_indexed_indirect(indirect_buffer, indirect_offset, count);
+        self.stats.draw_calls += count as u64;
+    }
+
+    /// Not counted, the number of draws lives on the gpu.
+    pub fn multi_draw_indirect_count(&mut self, indirect_buffer: &'a Buffer, indirect_offset: BufferAddress, count_buffer: &'a Buffer, count_offset: BufferAddress, max_count: u32) {
+        self.render_pass.multi_draw_indirect_count(indirect_buffer, indirect_offset, count_buffer, count_offset, max_count);
+    }
+
+    pub fn multi_draw_indexed_indirect_count(&mut self, indirect_buffer: &'a Buffer, indirect_offset: BufferAddress, count_buffer: &'a Buffer, count_offset: BufferAddress, max_count: u32) {
+        self.render_pass.multi_draw_indexed_indirect_count(indirect_buffer, indirect_offset, count_buffer, count_offset, max_count);
+    }
+
+    /// The draws inside the bundles aren't counted. Bundles reset the pass state,
+    /// so the next pipeline and bind groups are set again.
+    pub fn execute_bundles<I: IntoIterator<Item = &'a RenderBundle>>(&mut self, render_bundles: I) {
+        self.render_pass.execute_bundles(render_bundles);
+        self.pipeline = None;
+        self.bind_groups.clear();
+    }
+
+    pub fn set_blend_constant(&mut self, color: Color) {
+        self.render_pass.set_blend_constant(color);
+    }
+
+    pub fn set_scissor_rect(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        self.render_pass.set_scissor_rect(x, y, width, height);
+    }
+
+    pub fn set_viewport(&mut self, x: f32, y: f32, w: f32, h: f32, min_depth: f32, max_depth: f32) {
+        self.render_pass.set_viewport(x, y, w, h, min_depth, max_depth);
+    }
+
+    pub fn set_stencil_reference(&mut self, reference: u32) {
+        self.render_pass.set_stencil_reference(reference);
+    }
+
+    pub fn set_push_constants(&mut self, stages: ShaderStages, offset: u32, data: &[u8]) {
+        self.render_pass.set_push_constants(stages, offset, data);
+    }
+
+    pub fn insert_debug_marker(&mut self, label: &str) {
+        self.render_pass.insert_debug_marker(label);
+    }
+
+    pub fn push_debug_group(&mut self, label: &str) {
+        self.render_pass.push_debug_group(label);
+    }
+
+    pub fn pop_debug_group(&mut self) {
+        self.render_pass.pop_debug_group();
+    }
+
+    pub fn write_timestamp(&mut self, query_set: &QuerySet, query_index: u32) {
+        self.render_pass.write_timestamp(query_set, query_index);
+    }
+
+    pub fn begin_pipeline_statistics_query(&mut self, query_set: &QuerySet, query_index: u32) {
+        self.render_pass.begin_pipeline_statistics_query(query_set, query_index);
+    }
+
+    pub fn end_pipeline_statistics_query(&mut self) {
+        self.render_pass.end_pipeline_statistics_query();
+    }
+}
+
+impl Drop for StatsRenderPass<'_> {
+    fn drop(&mut self) {
+        self.frame_stats.record(&self.stats);
+    }
+}
@@ -0,0 +1,153 @@
+use wgpu::{CommandEncoder, RenderPass, TextureView};
+use crate::rendering::canvas::Canvas;
+use crate::util::textures::Texture;
+
+/// Describes the attachments a pipeline renders into, pipelines only work with passes of the same format.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TargetFormat {
+    pub color: wgpu::TextureFormat,
+    pub sample_count: u32,
+    pub depth: bool,
+}
+
+impl TargetFormat {
+    pub fn new(color: wgpu::TextureFormat) -> Self {
+        Self { color, sample_count: 1, depth: false }
+    }
+
+    pub fn with_sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    pub fn with_depth(mut self, depth: bool) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Pipelines that don't test depth still have to match the format of the attachment.
+    pub fn depth_stencil_state(&self, depth_test: bool) -> Option<wgpu::DepthStencilState> {
+        if !self.depth { return None; }
+
+        Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: depth_test,
+            depth_compare: if depth_test { wgpu::CompareFunction::Less } else { wgpu::CompareFunction::Always },
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        })
+    }
+
+    pub fn multisample_state(&self) -> wgpu::MultisampleState {
+        wgpu::MultisampleState {
+            count: self.sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        }
+    }
+}
+
+/// Offscreen color and optional depth attachment, e.g. for minimaps, mirrors or thumbnails.
+/// After rendering, `color` can be bound like any other texture.
+pub struct RenderTarget {
+    pub color: Texture,
+    pub depth: Option<Texture>,
+    // Only set when multisampling, gets resolved into `color` at the end of a pass
+    msaa_view: Option<TextureView>,
+    pub format: TargetFormat,
+    pub width: u32,
+    pub height: u32,
+    label: String,
+}
+
+impl RenderTarget {
+    pub fn new(canvas: &Canvas, width: u32, height: u32, format: TargetFormat, label: &str) -> Self {
+        let (color, depth, msaa_view) = Self::create_textures(canvas, width, height, &format, label);
+
+        Self {
+            color,
+            depth,
+            msaa_view,
+            format,
+            width,
+            height,
+            label: label.to_string(),
+        }
+    }
+
+    /// Target with the format of the canvas, so everything built for the canvas can draw into it.
+    pub fn for_canvas(canvas: &Canvas, width: u32, height: u32, label: &str) -> Self {
+        Self::new(canvas, width, height, canvas.target_format(), label)
+    }
+
+    fn create_textures(canvas: &Canvas, width: u32, height: u32, format: &TargetFormat, label: &str) -> (Texture, Option<Texture>, Option<TextureView>) {
+        let color = Texture::create_color_texture(
+            &canvas.device,
+            format.color,
+            width,
+            height,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            label,
+        );
+        let depth = if format.depth {
+            Some(Texture::create_depth_texture(&canvas.device, width, height, format.sample_count, label))
+        } else {
+            None
+        };
+        let msaa_view = if format.sample_count > 1 {
+            Some(Texture::create_msaa_target(&canvas.device, format.color, width, height, format.sample_count, label))
+        } else {
+            None
+        };
+
+        (color, depth, msaa_view)
+    }
+
+    pub fn resize(&mut self, canvas: &Canvas, width: u32, height: u32) {
+        if width == 0 || height == 0 { return; }
+
+        let (color, depth, msaa_view) = Self::create_textures(canvas, width, height, &self.format, &self.label);
+        self.color = color;
+        self.depth = depth;
+        self.msaa_view = msaa_view;
+        self.width = width;
+        self.height = height;
+    }
+
+    pub fn color_attachment(&self, load: wgpu::LoadOp<wgpu::Color>) -> wgpu::RenderPassColorAttachment<'_> {
+        let (view, resolve_target) = match &self.msaa_view {
+            Some(msaa_view) => (msaa_view, Some(&self.color.view)),
+            None => (&self.color.view, None),
+        };
+
+        wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target,
+            ops: wgpu::Operations {
+                load,
+                store: true,
+            },
+        }
+    }
+
+    pub fn depth_stencil_attachment(&self) -> Option<wgpu::RenderPassDepthStencilAttachment<'_>> {
+        self.depth.as_ref().map(|depth| wgpu::RenderPassDepthStencilAttachment {
+            view: &depth.view,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(1.0),
+                store: true,
+            }),
+            stencil_ops: None,
+        })
+    }
+
+    /// Begins a pass that clears the target. Once wrapped in a [`crate::rendering::render_stats::StatsRenderPass`],
+    /// [`crate::shape::shape_drawer::ShapeDrawer`] and [`crate::rendering::model::DrawModel`] draw into it like into the canvas.
+    pub fn begin_render_pass<'a>(&'a self, encoder: &'a mut CommandEncoder, clear_color: wgpu::Color) -> RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(&self.label),
+            color_attachments: &[Some(self.color_attachment(wgpu::LoadOp::Clear(clear_color)))],
+            depth_stencil_attachment: self.depth_stencil_attachment(),
+        })
+    }
+}
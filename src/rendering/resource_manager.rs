@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use crate::error::{HermitError, HermitResult};
+use crate::rendering::bind_group::BindLayout;
+use crate::rendering::canvas::Canvas;
+use crate::rendering::model::{Material, Mesh, Model};
+use crate::util::resources;
+use crate::util::textures::Texture;
+
+/// Refers to a `T` in a [`ResourcePool`]. Handles are cheap to copy and never dangle:
+/// once the resource is destroyed its slot gets a new generation and the old handles stop resolving.
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(index: u32, generation: u32) -> Self {
+        Self { index, generation, _marker: PhantomData }
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+// Derives would require T to implement the traits as well
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> Debug for Handle<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle<{}>({}v{})", std::any::type_name::<T>().rsplit("::").next().unwrap_or_default(), self.index, self.generation)
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+    ref_count: u32,
+    name: Option<String>,
+}
+
+/// Resources of one type, counted by reference. Inserting a resource holds one reference,
+/// [`ResourcePool::retain`] adds one and [`ResourcePool::release`] drops one. Releasing the last reference
+/// invalidates the handles right away but keeps the resource alive until [`ResourcePool::end_frame`],
+/// so a frame that was already recorded with it can still be submitted.
+pub struct ResourcePool<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+    names: HashMap<String, Handle<T>>,
+    // Released during the current frame, dropped when it ends
+    destroyed: Vec<T>,
+}
+
+impl<T> ResourcePool<T> {
+    pub fn new() -> Self {
+        Self { slots: vec![], free: vec![], names: HashMap::new(), destroyed: vec![] }
+    }
+
+    pub fn insert(&mut self, value: T) -> Handle<T> {
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.value = Some(value);
+                slot.ref_count = 1;
+                Handle::new(index, slot.generation)
+            }
+            None => {
+                self.slots.push(Slot { generation: 0, value: Some(value), ref_count: 1, name: None });
+                Handle::new(self.slots.len() as u32 - 1, 0)
+            }
+        }
+    }
+
+    /// Inserts `value` so it can be found by `name`, e.g. the path it was loaded from.
+    /// A resource already registered under the name keeps its handles but can't be found by name anymore.
+    pub fn insert_named(&mut self, name: &str, value: T) -> Handle<T> {
+        let handle = self.insert(value);
+        if let Some(previous) = self.names.insert(name.to_string(), handle) {
+            self.slots[previous.index as usize].name = None;
+        }
+        self.slots[handle.index as usize].name = Some(name.to_string());
+        handle
+    }
+
+    /// Looks up a resource by name without adding a reference.
+    pub fn find(&self, name: &str) -> Option<Handle<T>> {
+        self.names.get(name).copied()
+    }
+
+    pub fn name(&self, handle: Handle<T>) -> Option<&str> {
+        self.slot(handle)?.name.as_deref()
+    }
+
+    pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.slot(handle)?.value.as_ref()
+    }
+
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        let slot = self.slots.get_mut(handle.index as usize).filter(|slot| slot.generation == handle.generation)?;
+        slot.value.as_mut()
+    }
+
+    pub fn contains(&self, handle: Handle<T>) -> bool {
+        self.get(handle).is_some()
+    }
+
+    /// Adds a reference for another owner, returns the handle again or `None` if it was already destroyed.
+    pub fn retain(&mut self, handle: Handle<T>) -> Option<Handle<T>> {
+        let slot = self.slots.get_mut(handle.index as usize).filter(|slot| slot.generation == handle.generation && slot.value.is_some())?;
+        slot.ref_count += 1;
+        Some(handle)
+    }
+
+    /// Drops a reference, returns whether it was the last one and the resource is scheduled for destruction.
+    /// Stale handles are ignored.
+    pub fn release(&mut self, handle: Handle<T>) -> bool {
+        let slot = match self.slots.get_mut(handle.index as usize).filter(|slot| slot.generation == handle.generation && slot.value.is_some()) {
+            Some(slot) => slot,
+            None => return false,
+        };
+
+        slot.ref_count -= 1;
+        if slot.ref_count > 0 {
+            return false;
+        }
+
+        self.destroyed.extend(slot.value.take());
+        slot.generation = slot.generation.wrapping_add(1);
+        if let Some(name) = slot.name.take() {
+            self.names.remove(&name);
+        }
+        self.free.push(handle.index);
+        true
+    }
+
+    /// References held on the resource, 0 for stale handles.
+    pub fn ref_count(&self, handle: Handle<T>) -> u32 {
+        self.slot(handle).filter(|slot| slot.value.is_some()).map_or(0, |slot| slot.ref_count)
+    }
+
+    /// Resources that are still alive.
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Resources released this frame that haven't been dropped yet.
+    pub fn pending_destruction(&self) -> usize {
+        self.destroyed.len()
+    }
+
+    /// Drops everything released since the last call, after the frame that may still use them was submitted.
+    pub fn end_frame(&mut self) {
+        self.destroyed.clear();
+    }
+
+    fn slot(&self, handle: Handle<T>) -> Option<&Slot<T>> {
+        self.slots.get(handle.index as usize).filter(|slot| slot.generation == handle.generation)
+    }
+}
+
+impl<T> Default for ResourcePool<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Owns the gpu resources shared between the things drawn, handing out [`Handle`]s to them.
+/// Files are only loaded once, loading a path again adds a reference to what was loaded before.
+#[derive(Default)]
+pub struct ResourceManager {
+    pub textures: ResourcePool<Texture>,
+    pub meshes: ResourcePool<Mesh>,
+    pub materials: ResourcePool<Material>,
+    pub buffers: ResourcePool<wgpu::Buffer>,
+    models: HashMap<String, Model>,
+}
+
+impl ResourceManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the texture at `path` relative to the resource root, or adds a reference if it's already loaded.
+    pub async fn load_texture(&mut self, path: &str, canvas: &Canvas) -> HermitResult<Handle<Texture>> {
+        if let Some(handle) = self.textures.find(path).and_then(|handle| self.textures.retain(handle)) {
+            return Ok(handle);
+        }
+
+        let texture = resources::load_texture(path, &canvas.device, &canvas.queue).await
+            .map_err(|error| HermitError::AssetLoadFailed { name: path.to_string(), message: error.to_string() })?;
+        Ok(self.textures.insert_named(path, texture))
+    }
+
+    /// Loads the model at `path` with its materials bound with `layout`, or adds a reference to every part of it
+    /// if it's already loaded. Textures shared with other models are only loaded once.
+    pub async fn load_model(&mut self, path: &str, canvas: &Canvas, layout: &BindLayout) -> HermitResult<Model> {
+        if let Some(model) = self.models.get(path).cloned() {
+            if self.is_alive(&model) {
+                self.retain_model(&model);
+                return Ok(model);
+            }
+        }
+
+        let model = resources::load_model(path, canvas, layout, self).await
+            .map_err(|error| HermitError::AssetLoadFailed { name: path.to_string(), message: error.to_string() })?;
+        if self.is_alive(&model) {
+            self.models.insert(path.to_string(), model.clone());
+        }
+        Ok(model)
+    }
+
+    // A model without any parts holds no references, so there's nothing telling whether it was released
+    fn is_alive(&self, model: &Model) -> bool {
+        (!model.meshes.is_empty() || !model.materials.is_empty())
+            && model.meshes.iter().all(|mesh| self.meshes.contains(*mesh))
+            && model.materials.iter().all(|material| self.materials.contains(*material))
+    }
+
+    fn retain_model(&mut self, model: &Model) {
+        for mesh in model.meshes.iter() {
+            self.meshes.retain(*mesh);
+        }
+        for material in model.materials.iter() {
+            self.materials.retain(*material);
+        }
+    }
+
+    /// Drops the references `model` holds on its meshes and materials.
+    pub fn release_model(&mut self, model: &Model) {
+        for mesh in model.meshes.iter() {
+            self.meshes.release(*mesh);
+        }
+        for material in model.materials.iter() {
+            self.release_material(*material);
+        }
+        let models = std::mem::take(&mut self.models);
+        self.models = models.into_iter().filter(|(_, loaded)| self.is_alive(loaded)).collect();
+    }
+
+    /// Drops a reference to the material, once it's destroyed its texture is released as well.
+    pub fn release_material(&mut self, material: Handle<Material>) -> bool {
+        let texture = self.materials.get(material).map(|material| material.diffuse_texture);
+        let destroyed = self.materials.release(material);
+        if let (true, Some(texture)) = (destroyed, texture) {
+            self.textures.release(texture);
+        }
+        destroyed
+    }
+
+    /// Drops what was released during the frame, call it after the frame was submitted.
+    pub fn end_frame(&mut self) {
+        self.textures.end_frame();
+        self.meshes.end_frame();
+        self.materials.end_frame();
+        self.buffers.end_frame();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn released_slots_are_reused_with_a_new_generation() {
+        let mut pool = ResourcePool::new();
+        let first = pool.insert("first");
+        assert!(pool.release(first));
+
+        let second = pool.insert("second");
+        assert_eq!(second.index(), first.index());
+        assert_ne!(second.generation(), first.generation());
+        assert_eq!(pool.get(first), None);
+        assert_eq!(pool.get(second), Some(&"second"));
+    }
+
+    #[test]
+    fn stale_handles_are_ignored() {
+        let mut pool = ResourcePool::new();
+        let stale = pool.insert(1);
+        pool.release(stale);
+        let current = pool.insert(2);
+
+        assert!(!pool.release(stale));
+        assert_eq!(pool.retain(stale), None);
+        assert_eq!(pool.ref_count(stale), 0);
+        assert_eq!(pool.get_mut(stale), None);
+        assert_eq!(pool.ref_count(current), 1);
+    }
+
+    #[test]
+    fn destroyed_after_the_last_release() {
+        let mut pool = ResourcePool::new();
+        let handle = pool.insert(1);
+        assert_eq!(pool.retain(handle), Some(handle));
+        assert_eq!(pool.ref_count(handle), 2);
+
+        assert!(!pool.release(handle));
+        assert!(pool.contains(handle));
+        assert!(pool.release(handle));
+        assert!(!pool.contains(handle));
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn released_values_live_until_the_frame_ends() {
+        let mut pool = ResourcePool::new();
+        let handle = pool.insert(1);
+        pool.release(handle);
+        assert_eq!(pool.pending_destruction(), 1);
+
+        pool.end_frame();
+        assert_eq!(pool.pending_destruction(), 0);
+    }
+
+    #[test]
+    fn names_are_removed_with_the_resource() {
+        let mut pool = ResourcePool::new();
+        let handle = pool.insert_named("cube.png", 1);
+        assert_eq!(pool.find("cube.png"), Some(handle));
+        assert_eq!(pool.name(handle), Some("cube.png"));
+
+        pool.release(handle);
+        assert_eq!(pool.find("cube.png"), None);
+    }
+
+    #[test]
+    fn inserting_under_a_taken_name_replaces_the_lookup() {
+        let mut pool = ResourcePool::new();
+        let old = pool.insert_named("cube.png", 1);
+        let new = pool.insert_named("cube.png", 2);
+
+        assert_eq!(pool.find("cube.png"), Some(new));
+        assert_eq!(pool.name(old), None);
+        assert!(pool.contains(old));
+
+        // Releasing the old one mustn't take the name from the new one
+        pool.release(old);
+        assert_eq!(pool.find("cube.png"), Some(new));
+    }
+}
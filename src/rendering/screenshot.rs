@@ -0,0 +1,85 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+use crate::error::{HermitError, HermitResult};
+use crate::rendering::canvas::{Canvas, CanvasFrame};
+use crate::rendering::frame_capture::FrameCapture;
+
+/// Saves the next rendered frame as PNG.
+pub struct Screenshot {
+    pub key: VirtualKeyCode,
+    requested: Option<PathBuf>,
+    capture: FrameCapture,
+}
+
+impl Screenshot {
+    pub const DEFAULT_KEY: VirtualKeyCode = VirtualKeyCode::F12;
+
+    pub async fn new(canvas: &Canvas) -> HermitResult<Self> {
+        Ok(Self {
+            key: Self::DEFAULT_KEY,
+            requested: None,
+            capture: FrameCapture::new(canvas).await?,
+        })
+    }
+
+    pub fn with_key(mut self, key: VirtualKeyCode) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Saves the next frame to `path`.
+    pub fn request(&mut self, path: impl AsRef<Path>) {
+        self.requested = Some(path.as_ref().to_path_buf());
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.is_some()
+    }
+
+    /// `screenshot-<unix millis>.png` in the working directory.
+    pub fn timestamped_path() -> PathBuf {
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_millis()).unwrap_or(0);
+        PathBuf::from(format!("screenshot-{}.png", millis))
+    }
+
+    /// Requests a screenshot when [`Screenshot::key`] gets pressed, returns whether the event was used.
+    pub fn handle_input(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(key),
+                    ..
+                },
+                ..
+            } if *key == self.key => {
+                self.request(Self::timestamped_path());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Makes `frame` render into a readable texture if a screenshot is pending, call right after [`Canvas::frame`].
+    pub fn redirect(&mut self, canvas: &Canvas, frame: &mut CanvasFrame) {
+        if self.requested.is_some() {
+            self.capture.redirect(canvas, frame);
+        }
+    }
+
+    /// Writes the pending screenshot, call before [`CanvasFrame::present`].
+    /// Returns where the screenshot was saved, `None` if no screenshot was pending.
+    pub fn finish(&mut self, canvas: &Canvas, frame: &mut CanvasFrame) -> HermitResult<Option<PathBuf>> {
+        let image = match self.capture.finish(canvas, frame)? {
+            Some(image) => image,
+            None => return Ok(None),
+        };
+        let path = self.requested.take().expect("Redirected frame without request");
+
+        image.save(&path).map_err(|error| HermitError::CaptureFailed(format!("{}: {}", path.display(), error)))?;
+
+        log::info!("Saved screenshot to {}", path.display());
+        Ok(Some(path))
+    }
+}
@@ -1,5 +1,9 @@
+use std::sync::atomic::{AtomicU64, Ordering};
 use wgpu::{ShaderModule, VertexBufferLayout, VertexState};
+use crate::error::{HermitError, HermitResult};
 use crate::rendering::canvas::Canvas;
+use crate::rendering::preprocessor::{preprocess, PreprocessedShader, ShaderDefines};
+use crate::rendering::reflection::ShaderReflection;
 use crate::util::resources::load_string;
 
 pub struct VertexEntry {}
@@ -28,21 +32,87 @@ impl<'a> FragmentEntry<'a> {
     }
 }
 
+static NEXT_SHADER_ID: AtomicU64 = AtomicU64::new(0);
+
 pub struct Shader {
     pub shader_mod: ShaderModule,
+    id: u64,
+    // Files the source was assembled from, checked by hot reloading
+    dependencies: Vec<String>,
+    reflection: ShaderReflection,
 }
 
 impl Shader {
-    pub async fn new(path: &str, canvas: &Canvas) -> Self {
-        let mut error = String::from("Cannot open: ");
-        error.push_str(path);
-        let shader_text = load_string(path).await.expect(error.as_str());
+    /// Loads the shader at `path` and resolves its preprocessor directives, see [`preprocess`].
+    pub async fn new(path: &str, canvas: &Canvas) -> HermitResult<Self> {
+        Self::with_defines(path, &ShaderDefines::new(), canvas).await
+    }
+
+    /// Like [`Shader::new`] with `defines` set before preprocessing, to specialize a shared shader.
+    pub async fn with_defines(path: &str, defines: &ShaderDefines, canvas: &Canvas) -> HermitResult<Self> {
+        let preprocessed = preprocess(path, defines).await?;
+
+        Self::from_preprocessed(&preprocessed, path, canvas).await
+    }
 
+    /// Compiles WGSL that didn't come from a single file as is, `label` is used in errors in place of a path.
+    pub async fn from_source(source: &str, label: &str, canvas: &Canvas) -> HermitResult<Self> {
+        Self::from_preprocessed(&PreprocessedShader::unprocessed(label, source), label, canvas).await
+    }
+
+    /// Compiles preprocessed WGSL, locations in errors point into the files it was assembled from.
+    /// The source is parsed and validated with naga first, so mistakes come back as a
+    /// [`HermitError::ShaderInvalid`] with the offending line instead of reaching the device.
+    pub async fn from_preprocessed(preprocessed: &PreprocessedShader, label: &str, canvas: &Canvas) -> HermitResult<Self> {
+        let reflection = ShaderReflection::from_preprocessed(preprocessed, label, canvas.device.features())?;
+
+        // Catch validation errors here instead of letting the device panic on them
+        canvas.device.push_error_scope(wgpu::ErrorFilter::Validation);
         let shader = canvas.device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some(path),
-            source: wgpu::ShaderSource::Wgsl(shader_text.as_str().into()),
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(preprocessed.source.as_str().into()),
         });
+        if let Some(error) = canvas.device.pop_error_scope().await {
+            let message = match error {
+                wgpu::Error::Validation { description, .. } => preprocessed.map_message(&description),
+                error => error.to_string(),
+            };
+            return Err(HermitError::ShaderCompileFailed { path: label.to_string(), message });
+        }
+
+        let mut dependencies = preprocessed.files.clone();
+        dependencies.sort();
+        dependencies.dedup();
+
+        Ok(Self { shader_mod: shader, id: NEXT_SHADER_ID.fetch_add(1, Ordering::Relaxed), dependencies, reflection })
+    }
+
+    pub(crate) async fn load_source(path: &str) -> HermitResult<String> {
+        load_string(path).await.map_err(|error| {
+            match error.downcast_ref::<std::io::Error>() {
+                Some(io_error) if io_error.kind() == std::io::ErrorKind::NotFound => HermitError::AssetNotFound(path.to_string()),
+                _ => HermitError::ShaderLoadFailed { path: path.to_string(), message: error.to_string() },
+            }
+        })
+    }
+
+    /// Unique for every loaded shader, used to tell shaders apart in caches.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Bindings and vertex inputs the shader declares.
+    pub fn reflection(&self) -> &ShaderReflection {
+        &self.reflection
+    }
+
+    /// Files the shader was assembled from, including itself.
+    pub fn dependencies(&self) -> &[String] {
+        &self.dependencies
+    }
 
-        Self { shader_mod: shader }
+    /// Whether one of the `changed` paths went into this shader.
+    pub fn depends_on(&self, changed: &[String]) -> bool {
+        changed.iter().any(|path| self.dependencies.contains(path))
     }
 }
\ No newline at end of file
@@ -0,0 +1,85 @@
+use std::num::NonZeroU64;
+use std::sync::Mutex;
+use wgpu::{Buffer, BufferAddress, CommandEncoder, Device};
+use wgpu::util::StagingBelt;
+
+/// Uploads done between two recalls of an [`Uploader`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct UploadStats {
+    pub writes: u64,
+    pub bytes: u64,
+}
+
+/// Batches buffer writes into a command encoder through a [`StagingBelt`], so many small updates share
+/// a few mapped staging chunks instead of each going through the queue on its own.
+/// Every frame the writes go into the frame's encoder, then [`Uploader::finish`] is called before the
+/// encoder is submitted and [`Uploader::recall`] after, which hands the chunks back for reuse.
+/// The [`crate::rendering::render_graph::RenderGraph`] does both for the canvas uploader.
+pub struct Uploader {
+    belt: Mutex<StagingBelt>,
+    current: Mutex<UploadStats>,
+    last: Mutex<UploadStats>,
+}
+
+impl Uploader {
+    /// Big enough for a frame of uniform and instance updates in a single chunk.
+    pub const DEFAULT_CHUNK_SIZE: BufferAddress = 256 * 1024;
+
+    /// Writes bigger than `chunk_size` get a staging buffer of their own.
+    pub fn new(chunk_size: BufferAddress) -> Self {
+        Self {
+            belt: Mutex::new(StagingBelt::new(chunk_size)),
+            current: Mutex::new(UploadStats::default()),
+            last: Mutex::new(UploadStats::default()),
+        }
+    }
+
+    /// Records a copy of `data` into `buffer` at `offset`, the buffer needs [`wgpu::BufferUsages::COPY_DST`].
+    /// Like [`wgpu::Queue::write_buffer`] the offset and size have to be multiples of [`wgpu::COPY_BUFFER_ALIGNMENT`].
+    pub fn write(&self, device: &Device, encoder: &mut CommandEncoder, buffer: &Buffer, offset: BufferAddress, data: &[u8]) {
+        let size = match NonZeroU64::new(data.len() as u64) {
+            Some(size) => size,
+            None => return,
+        };
+        assert!(offset.is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT) && size.get().is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT),
+            "Uploads have to start and end on multiples of {} bytes", wgpu::COPY_BUFFER_ALIGNMENT);
+
+        let mut belt = self.belt.lock().unwrap();
+        belt.write_buffer(encoder, buffer, offset, size, device).copy_from_slice(data);
+
+        let mut current = self.current.lock().unwrap();
+        current.writes += 1;
+        current.bytes += size.get();
+    }
+
+    /// Unmaps the chunks written to, has to happen before the encoders holding the writes are submitted.
+    pub fn finish(&self) {
+        self.belt.lock().unwrap().finish();
+    }
+
+    /// Takes the chunks back once the gpu is done with them, after the encoders were submitted.
+    /// Finishes the frame's stats and returns them.
+    pub fn recall(&self) -> UploadStats {
+        self.belt.lock().unwrap().recall();
+
+        let finished = std::mem::take(&mut *self.current.lock().unwrap());
+        *self.last.lock().unwrap() = finished;
+        finished
+    }
+
+    /// What was written since the last recall.
+    pub fn current(&self) -> UploadStats {
+        *self.current.lock().unwrap()
+    }
+
+    /// What the last frame wrote.
+    pub fn last_frame(&self) -> UploadStats {
+        *self.last.lock().unwrap()
+    }
+}
+
+impl Default for Uploader {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CHUNK_SIZE)
+    }
+}
@@ -1,17 +1,20 @@
-use wgpu::{BindGroup, BindGroupLayout, Buffer, BufferUsages, FragmentState, RenderPass, RenderPipeline, ShaderModule, VertexState};
+use std::sync::Arc;
+use wgpu::{BindGroup, Buffer, BufferUsages, RenderPipeline};
 use wgpu::IndexFormat::Uint16;
-use crate::INDICES2;
+use crate::error::HermitResult;
+use crate::rendering::bind_group::BindLayout;
 use crate::rendering::buffer::BufferBuilder;
 use crate::rendering::canvas::Canvas;
 use crate::rendering::pipeline::Pipeline;
+use crate::rendering::render_stats::StatsRenderPass;
 use crate::rendering::shader::{FragmentEntry, Shader, VertexEntry};
 
 pub struct ShapeDrawer<'a, 'b> where 'a: 'b {
-    render_pass: &'b mut RenderPass<'a>,
+    render_pass: &'b mut StatsRenderPass<'a>,
 }
 
 impl<'a, 'b> ShapeDrawer<'a, 'b> {
-    pub fn new(render_pass: &'b mut RenderPass<'a>) -> Self {
+    pub fn new(render_pass: &'b mut StatsRenderPass<'a>) -> Self {
         Self { render_pass }
     }
 
@@ -21,7 +24,36 @@ impl<'a, 'b> ShapeDrawer<'a, 'b> {
 }
 
 pub trait Shape<'a> {
-    fn draw<'b>(&'a self, render_pass: &'b mut RenderPass<'a>) where 'a: 'b;
+    fn pipeline(&'a self) -> &'a RenderPipeline;
+    /// Sets the shape's pipeline before drawing. Shapes share pipelines through the cache,
+    /// so the pass skips the switch if it's already the current one.
+    fn draw<'b>(&'a self, render_pass: &'b mut StatsRenderPass<'a>) where 'a: 'b;
+
+    /// Builds the pipeline again from `shader`, e.g. after it was reloaded. Keeps the old one if that fails.
+    fn rebuild_pipeline(&mut self, shader: &Shader, canvas: &Canvas) -> HermitResult<()>;
+}
+
+// Textured shapes bind their texture at group 0, laid out like BindLayout::texture_2d
+fn shape_pipeline(shader: &Shader, texture: &Option<(BindLayout, BindGroup)>, canvas: &Canvas) -> HermitResult<Arc<RenderPipeline>> {
+    let (layouts, entries) = match texture {
+        Some((layout, _)) => (vec![&layout.layout], vec![layout.entries()]),
+        None => (vec![], vec![]),
+    };
+
+    Pipeline::builder(canvas,
+                      VertexEntry::new(&shader.shader_mod, "vs_main", &[Vertex::desc()]),
+                      FragmentEntry::new(&shader.shader_mod, "fs_main"),
+    )
+        .label(Some("Shape Pipeline"))
+        .group_layouts(layouts.as_slice())
+        .validate(shader, entries.as_slice())?
+        .build_cached_checked(shader, entries.as_slice())
+}
+
+// Only replaces `pipeline` if the new one was built without errors
+fn rebuild_shape_pipeline(pipeline: &mut Arc<RenderPipeline>, shader: &Shader, texture: &Option<(BindLayout, BindGroup)>, canvas: &Canvas) -> HermitResult<()> {
+    *pipeline = shape_pipeline(shader, texture, canvas)?;
+    Ok(())
 }
 
 pub struct ShapeData<'a> {
@@ -39,38 +71,34 @@ pub struct Rectangle<'a> {
     vertices: &'a [Vertex],
     vertex_buffer: Buffer,
     index_buffer: Buffer,
-    pipeline: RenderPipeline,
-    texture: Option<(BindGroupLayout,BindGroup)>,
+    pipeline: Arc<RenderPipeline>,
+    texture: Option<(BindLayout, BindGroup)>,
 }
 
 impl<'a> Rectangle<'a> {
     const INDICES_RECTANGLE: &'a [u16] = &[0, 2, 1, 0, 3, 2];
 
-    pub fn new(shader: &Shader, vertices: &'a [Vertex], texture: Option<(BindGroupLayout, BindGroup)>, canvas: &Canvas) -> Self {
+    pub fn new(shader: &Shader, vertices: &'a [Vertex], texture: Option<(BindLayout, BindGroup)>, canvas: &Canvas) -> HermitResult<Self> {
         let vertex = BufferBuilder::new(vertices, BufferUsages::VERTEX, Some("Vertex"), canvas);
         let index = BufferBuilder::new(Rectangle::INDICES_RECTANGLE, BufferUsages::INDEX, Some("Index"), canvas);
 
-        let layouts = if texture.is_some() {
-            vec![&texture.as_ref().unwrap().0]
-        } else {
-            vec![]
-        };
-
-        let pipeline = Pipeline::new(&canvas,
-            layouts.as_slice(),
-            Some("Pipeline Rectangle"),
-            VertexEntry::new(&shader.shader_mod, "vs_main", &[Vertex::desc()]),
-            FragmentEntry::new(&shader.shader_mod, "fs_main"),
-        );
+        let pipeline = shape_pipeline(shader, &texture, canvas)?;
 
-        Self { vertices, vertex_buffer: vertex, index_buffer: index, pipeline, texture }
+        Ok(Self { vertices, vertex_buffer: vertex, index_buffer: index, pipeline, texture })
     }
 }
 
 impl<'a> Shape<'a> for Rectangle<'a> {
-    fn draw<'b>(&'a self, render_pass: &'b mut RenderPass<'a>) where 'a: 'b {
-        render_pass.set_pipeline(&self.pipeline);
+    fn pipeline(&'a self) -> &'a RenderPipeline {
+        &self.pipeline
+    }
+
+    fn rebuild_pipeline(&mut self, shader: &Shader, canvas: &Canvas) -> HermitResult<()> {
+        rebuild_shape_pipeline(&mut self.pipeline, shader, &self.texture, canvas)
+    }
 
+    fn draw<'b>(&'a self, render_pass: &'b mut StatsRenderPass<'a>) where 'a: 'b {
+        render_pass.set_pipeline(&self.pipeline);
         if self.texture.is_some() {
             render_pass.set_bind_group(0, &self.texture.as_ref().unwrap().1, &[]);
         }
@@ -85,38 +113,34 @@ pub struct Triangle<'a> {
     vertices: &'a [Vertex],
     vertex_buffer: Buffer,
     index_buffer: Buffer,
-    pipeline: RenderPipeline,
-    texture: Option<(BindGroupLayout,BindGroup)>,
+    pipeline: Arc<RenderPipeline>,
+    texture: Option<(BindLayout, BindGroup)>,
 }
 
 impl<'a> Triangle<'a> {
     const INDICES_TRIANGLE: &'a [u16] = &[0, 1, 2];
 
-    pub fn new(shader: &Shader, vertices: &'a [Vertex], texture: Option<(BindGroupLayout, BindGroup)>, canvas: &Canvas) -> Self {
+    pub fn new(shader: &Shader, vertices: &'a [Vertex], texture: Option<(BindLayout, BindGroup)>, canvas: &Canvas) -> HermitResult<Self> {
         let vertex = BufferBuilder::new(vertices, BufferUsages::VERTEX, Some("Vertex"), canvas);
         let index = BufferBuilder::new(Triangle::INDICES_TRIANGLE, BufferUsages::INDEX, Some("Index"), canvas);
 
-        let layouts = if texture.is_some() {
-            vec![&texture.as_ref().unwrap().0]
-        } else {
-            vec![]
-        };
-
-        let pipeline = Pipeline::new(&canvas,
-                                     layouts.as_slice(),
-                                     Some("Pipeline Triangle"),
-                                     VertexEntry::new(&shader.shader_mod, "vs_main", &[Vertex::desc()]),
-                                     FragmentEntry::new(&shader.shader_mod, "fs_main"),
-        );
+        let pipeline = shape_pipeline(shader, &texture, canvas)?;
 
-        Self { vertices: vertices, vertex_buffer: vertex, index_buffer: index, pipeline, texture }
+        Ok(Self { vertices: vertices, vertex_buffer: vertex, index_buffer: index, pipeline, texture })
     }
 }
 
 impl<'a> Shape<'a> for Triangle<'a> {
-    fn draw<'b>(&'a self, render_pass: &'b mut RenderPass<'a>) where 'a: 'b {
-        render_pass.set_pipeline(&self.pipeline);
+    fn pipeline(&'a self) -> &'a RenderPipeline {
+        &self.pipeline
+    }
+
+    fn rebuild_pipeline(&mut self, shader: &Shader, canvas: &Canvas) -> HermitResult<()> {
+        rebuild_shape_pipeline(&mut self.pipeline, shader, &self.texture, canvas)
+    }
 
+    fn draw<'b>(&'a self, render_pass: &'b mut StatsRenderPass<'a>) where 'a: 'b {
+        render_pass.set_pipeline(&self.pipeline);
         render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
         render_pass.set_index_buffer(self.index_buffer.slice(..), Uint16);
         render_pass.draw_indexed(0..3, 0,0..1);
@@ -128,38 +152,34 @@ pub struct Polygon<'a> {
     indices: &'a [u16],
     vertex_buffer: Buffer,
     index_buffer: Buffer,
-    pipeline: RenderPipeline,
+    pipeline: Arc<RenderPipeline>,
     num_indices: u32,
-    texture: Option<(BindGroupLayout,BindGroup)>,
+    texture: Option<(BindLayout, BindGroup)>,
 }
 
 impl<'a> Polygon<'a> {
-    pub fn new(shader: &Shader, vertices: &'a [Vertex], indices: &'a [u16], texture: Option<(BindGroupLayout, BindGroup)>, canvas: &Canvas) -> Self {
+    pub fn new(shader: &Shader, vertices: &'a [Vertex], indices: &'a [u16], texture: Option<(BindLayout, BindGroup)>, canvas: &Canvas) -> HermitResult<Self> {
         let vertex = BufferBuilder::new(vertices, BufferUsages::VERTEX, Some("Vertex"), canvas);
         let index = BufferBuilder::new(indices, BufferUsages::INDEX, Some("Index"), canvas);
 
-        let layouts = if texture.is_some() {
-            vec![&texture.as_ref().unwrap().0]
-        } else {
-            vec![]
-        };
-
-        let pipeline = Pipeline::new(&canvas,
-                                     layouts.as_slice(),
-                                     Some("Pipeline Polygon"),
-                                     VertexEntry::new(&shader.shader_mod, "vs_main", &[Vertex::desc()]),
-                                     FragmentEntry::new(&shader.shader_mod, "fs_main"),
-        );
+        let pipeline = shape_pipeline(shader, &texture, canvas)?;
 
-        Self { vertices, indices, vertex_buffer: vertex, index_buffer: index, pipeline, num_indices: indices.len() as u32, texture }
+        Ok(Self { vertices, indices, vertex_buffer: vertex, index_buffer: index, pipeline, num_indices: indices.len() as u32, texture })
     }
 }
 
 impl<'a> Shape<'a> for Polygon<'a> {
 
-    fn draw<'b>(&'a self, render_pass: &'b mut RenderPass<'a>) where 'a: 'b {
-        render_pass.set_pipeline(&self.pipeline);
+    fn pipeline(&'a self) -> &'a RenderPipeline {
+        &self.pipeline
+    }
 
+    fn rebuild_pipeline(&mut self, shader: &Shader, canvas: &Canvas) -> HermitResult<()> {
+        rebuild_shape_pipeline(&mut self.pipeline, shader, &self.texture, canvas)
+    }
+
+    fn draw<'b>(&'a self, render_pass: &'b mut StatsRenderPass<'a>) where 'a: 'b {
+        render_pass.set_pipeline(&self.pipeline);
         render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
         render_pass.set_index_buffer(self.index_buffer.slice(..), Uint16);
         render_pass.draw_indexed(0..self.num_indices, 0,0..1);
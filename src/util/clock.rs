@@ -0,0 +1,51 @@
+use std::time::{Duration, Instant};
+
+/// Simulated time of the engine, advanced once per update.
+/// Normally follows the wall clock, with a fixed timestep it advances by exactly that much per update
+/// no matter how long the frame actually took.
+pub struct Clock {
+    last_tick: Instant,
+    delta: Duration,
+    elapsed: Duration,
+    frame: u64,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self {
+            last_tick: Instant::now(),
+            delta: Duration::ZERO,
+            elapsed: Duration::ZERO,
+            frame: 0,
+        }
+    }
+
+    /// Advances the clock by `fixed_timestep`, or by the wall time since the last tick if it is `None`.
+    pub fn tick(&mut self, fixed_timestep: Option<Duration>) {
+        let now = Instant::now();
+        self.delta = fixed_timestep.unwrap_or(now - self.last_tick);
+        self.last_tick = now;
+        self.elapsed += self.delta;
+        self.frame += 1;
+    }
+
+    /// Simulated time between the last two ticks.
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    /// Simulated time since the clock was created.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+}
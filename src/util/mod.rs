@@ -1,2 +1,3 @@
 pub mod textures;
 pub mod resources;
+pub mod clock;
@@ -3,7 +3,10 @@ use std::io::{BufReader, Cursor};
 use cfg_if::cfg_if;
 use wgpu::util::DeviceExt;
 
+use crate::rendering::bind_group::BindLayout;
+use crate::rendering::canvas::Canvas;
 use crate::rendering::model;
+use crate::rendering::resource_manager::ResourceManager;
 use crate::util::textures;
 use crate::util::textures::Texture;
 
@@ -19,6 +22,29 @@ fn format_url(file_name: &str) -> reqwest::Url {
     base.join(file_name).unwrap()
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+static RESOURCE_ROOT: std::sync::RwLock<Option<std::path::PathBuf>> = std::sync::RwLock::new(None);
+
+/// The `res` directory next to `Cargo.toml`, instead of the copy `build.rs` makes.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn source_resource_root() -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("res")
+}
+
+/// Loads resources from `root` from now on, `None` goes back to the copy in `OUT_DIR`.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn set_resource_root(root: Option<std::path::PathBuf>) {
+    *RESOURCE_ROOT.write().unwrap() = root;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn resource_path(file_name: &str) -> std::path::PathBuf {
+    match &*RESOURCE_ROOT.read().unwrap() {
+        Some(root) => root.join(file_name),
+        None => std::path::Path::new(env!("OUT_DIR")).join("res").join(file_name),
+    }
+}
+
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
     cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
@@ -28,10 +54,7 @@ pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
                 .text()
                 .await?;
         } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("res")
-                .join(file_name);
-            let txt = std::fs::read_to_string(path)?;
+            let txt = std::fs::read_to_string(resource_path(file_name))?;
         }
     }
 
@@ -48,10 +71,7 @@ pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
                 .await?
                 .to_vec();
         } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("res")
-                .join(file_name);
-            let data = std::fs::read(path)?;
+            let data = std::fs::read(resource_path(file_name))?;
         }
     }
 
@@ -67,12 +87,34 @@ pub async fn load_texture(
     textures::Texture::from_bytes(device, queue, &data, file_name)
 }
 
+/// Loads the meshes and materials into `manager`, the textures through it so ones already loaded are shared.
+/// Prefer [`ResourceManager::load_model`], which only loads each file once.
 pub async fn load_model(
     file_name: &str,
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-    layout: &wgpu::BindGroupLayout,
+    canvas: &Canvas,
+    layout: &BindLayout,
+    manager: &mut ResourceManager,
 ) -> anyhow::Result<model::Model> {
+    let mut model = model::Model { meshes: vec![], materials: vec![] };
+    match load_model_parts(file_name, canvas, layout, manager, &mut model).await {
+        Ok(()) => Ok(model),
+        Err(error) => {
+            // Drops what was loaded before the error, the textures go with their materials
+            manager.release_model(&model);
+            Err(error)
+        }
+    }
+}
+
+// Adds every material and mesh to `model` as soon as it's in the manager, so they can be released on failure
+async fn load_model_parts(
+    file_name: &str,
+    canvas: &Canvas,
+    layout: &BindLayout,
+    manager: &mut ResourceManager,
+    model: &mut model::Model,
+) -> anyhow::Result<()> {
+    let device = &canvas.device;
     let obj_text = load_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
@@ -91,72 +133,61 @@ pub async fn load_model(
     )
         .await?;
 
-    let mut materials = Vec::new();
     for m in obj_materials? {
-        let diffuse_texture = load_texture(&m.diffuse_texture, device, queue).await?;
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: None,
-        });
-
-        materials.push(model::Material {
+        let diffuse_texture = manager.load_texture(&m.diffuse_texture, canvas).await?;
+        let texture = manager.textures.get(diffuse_texture).unwrap();
+        let bind_group = layout.group(canvas, &[
+            wgpu::BindingResource::TextureView(&texture.view),
+            wgpu::BindingResource::Sampler(&texture.sampler),
+        ]);
+
+        model.materials.push(manager.materials.insert(model::Material {
             name: m.name,
             diffuse_texture,
             bind_group,
-        })
+        }))
+    }
+
+    for m in models {
+        let material = *model.materials.get(m.mesh.material_id.unwrap_or(0))
+            .ok_or_else(|| anyhow::anyhow!("Mesh {} has no material", m.name))?;
+        let vertices = (0..m.mesh.positions.len() / 3)
+            .map(|i| model::ModelVertex {
+                position: [
+                    m.mesh.positions[i * 3],
+                    m.mesh.positions[i * 3 + 1],
+                    m.mesh.positions[i * 3 + 2],
+                ],
+                tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
+                normal: [
+                    m.mesh.normals[i * 3],
+                    m.mesh.normals[i * 3 + 1],
+                    m.mesh.normals[i * 3 + 2],
+                ],
+            })
+            .collect::<Vec<_>>();
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Vertex Buffer", file_name)),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", file_name)),
+            contents: bytemuck::cast_slice(&m.mesh.indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+
+
+        model.meshes.push(manager.meshes.insert(model::Mesh {
+            name: file_name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            num_elements: m.mesh.indices.len() as u32,
+            material,
+        }));
     }
 
-    let meshes = models
-        .into_iter()
-        .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| model::ModelVertex {
-                    position: [
-                        m.mesh.positions[i * 3],
-                        m.mesh.positions[i * 3 + 1],
-                        m.mesh.positions[i * 3 + 2],
-                    ],
-                    tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
-                })
-                .collect::<Vec<_>>();
-
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-
-
-            model::Mesh {
-                name: file_name.to_string(),
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
-            }
-        })
-        .collect::<Vec<_>>();
-
-    Ok(model::Model { meshes, materials })
+    Ok(())
 }
\ No newline at end of file
@@ -5,16 +5,24 @@ use winit::window::WindowBuilder;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 use crate::Engine;
+use crate::error::HermitResult;
+use crate::rendering::canvas::CanvasOptions;
 
 pub struct WindowData {
     pub resizeable: bool,
     pub title: String,
     pub size: PhysicalSize<i32>,
+    pub canvas_options: CanvasOptions,
 }
 
 impl WindowData {
     pub fn new(resizeable: bool, title: String, size: PhysicalSize<i32>) -> Self {
-        Self { resizeable, title, size }
+        Self { resizeable, title, size, canvas_options: CanvasOptions::default() }
+    }
+
+    pub fn with_canvas_options(mut self, canvas_options: CanvasOptions) -> Self {
+        self.canvas_options = canvas_options;
+        self
     }
 }
 
@@ -22,7 +30,7 @@ pub struct HermitWindow {
 }
 
 impl HermitWindow {
-    pub async fn new(data: WindowData) -> (EventLoop<()>, winit::window::Window) {
+    pub async fn new(data: WindowData) -> HermitResult<(EventLoop<()>, winit::window::Window)> {
         cfg_if::cfg_if! {
             if #[cfg(target_arch = "wasm32")] {
                 std::panic::set_hook(Box::new(console_error_panic_hook::hook));
@@ -37,8 +45,7 @@ impl HermitWindow {
             .with_resizable(data.resizeable)
             .with_inner_size(data.size)
             .with_title(data.title)
-            .build(&event_loop)
-            .unwrap();
+            .build(&event_loop)?;
 
         #[cfg(target_arch = "wasm32")]
         {
@@ -56,11 +63,11 @@ impl HermitWindow {
                     dst.append_child(&canvas).ok()?;
                     Some(())
                 })
-                .expect("Couldn't append canvas to document body.");
+                .ok_or_else(|| crate::error::HermitError::SurfaceFailed("Couldn't append canvas to document body.".to_string()))?;
         }
 
-        let _state = Engine::new(&window).await;
+        let _state = Engine::new(&window, &data.canvas_options).await?;
 
-        (event_loop, window)
+        Ok((event_loop, window))
     }
 }
\ No newline at end of file